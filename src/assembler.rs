@@ -0,0 +1,536 @@
+//! An assembler for Cowgod-style CHIP-8 mnemonics.
+//!
+//! Each line holds an optional ``label:``, then an instruction or directive, then an optional
+//! ``;`` comment. Mnemonics and registers are case-insensitive. Supported directives are:
+//!
+//! * ``NAME = expr`` or ``NAME EQU expr`` defines a constant
+//! * ``db expr, ...`` and ``dw expr, ...`` emit bytes and big-endian words
+//! * ``include "file"`` assembles another file in place, relative to the including file
+//!
+//! Expressions are numbers (``42``, ``0x2A``, ``$2A``, ``0b101010``), labels and constants,
+//! combined with ``+`` and ``-``. The output of the disassembler assembles back to the same ROM.
+
+use crate::cpu::Opcode;
+use crate::disasm::PROGRAM_START;
+use crate::error::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ux::{u12, u4};
+
+// Deepest nesting of included files, which also stops a file from including itself forever
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An assembled ROM and the addresses of its labels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Program {
+    /* Renders the labels as a symbol file, one ``ADDRESS NAME`` line per label in address order */
+    pub fn symbol_file(&self) -> String {
+        let mut symbols: Vec<(&u16, &String)> =
+            self.symbols.iter().map(|(name, addr)| (addr, name)).collect();
+        symbols.sort();
+        symbols
+            .iter()
+            .map(|(addr, name)| format!("0x{:03X} {}\n", addr, name))
+            .collect()
+    }
+}
+
+/// Where a statement came from, for error messages
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, reason: impl Into<String>) -> Chip8Error {
+        Chip8Error::AssemblyError {
+            file: self.file.clone(),
+            line: self.line,
+            reason: reason.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Body {
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    location: Location,
+    addr: u16,
+    body: Body,
+}
+
+/// An operand of an instruction, after the registers and keywords have been recognised
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Long(String),
+    Expr(String),
+}
+
+fn parse_operand(text: &str) -> Operand {
+    let upper = text.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => return Operand::I,
+        "[I]" => return Operand::IndirectI,
+        "DT" => return Operand::DT,
+        "ST" => return Operand::ST,
+        "K" => return Operand::K,
+        "F" => return Operand::F,
+        "HF" => return Operand::HF,
+        "B" => return Operand::B,
+        "R" => return Operand::R,
+        _ => {}
+    }
+    if let Some(register) = upper.strip_prefix('V') {
+        if register.len() == 1 {
+            if let Some(digit) = register.chars().next().and_then(|c| c.to_digit(16)) {
+                return Operand::V(digit as u8);
+            }
+        }
+    }
+    match upper.strip_prefix("LONG ") {
+        Some(_) => Operand::Long(text[5..].trim().to_string()),
+        None => Operand::Expr(text.to_string()),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    text.split(',').map(|operand| operand.trim().to_string()).collect()
+}
+
+/// Assembles source text into a ROM loaded at 0x200
+#[derive(Debug, Default)]
+pub struct Assembler {
+    statements: Vec<Statement>,
+    labels: BTreeMap<String, u16>,
+    constants: BTreeMap<String, (String, Location)>,
+    addr: u32,
+}
+
+impl Assembler {
+    /* Assembles ``source``. Includes are resolved relative to the current directory */
+    pub fn assemble(source: &str) -> Result<Program> {
+        let mut assembler = Assembler::new();
+        assembler.read_source(source, "<source>", Path::new("."), 0)?;
+        assembler.finish()
+    }
+
+    /* Assembles the file at ``path``. Includes are resolved relative to the file */
+    pub fn assemble_file(path: &str) -> Result<Program> {
+        let mut assembler = Assembler::new();
+        assembler.read_file(Path::new(path), 0)?;
+        assembler.finish()
+    }
+
+    fn new() -> Self {
+        Assembler {
+            addr: u32::from(PROGRAM_START),
+            ..Assembler::default()
+        }
+    }
+
+    fn read_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        let source = fs::read_to_string(path).map_err(|err| Chip8Error::AssemblyError {
+            file: path.display().to_string(),
+            line: 0,
+            reason: format!("could not read file: {err}"),
+        })?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.read_source(&source, &path.display().to_string(), &dir, depth)
+    }
+
+    // First pass: split the source into statements and give every label an address
+    fn read_source(&mut self, source: &str, file: &str, dir: &Path, depth: usize) -> Result<()> {
+        for (number, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.to_string(),
+                line: number + 1,
+            };
+            let mut text = line.split(';').next().unwrap().trim();
+
+            if let Some((label, rest)) = text.split_once(':') {
+                let label = label.trim();
+                if is_identifier(label) {
+                    self.define_label(label, &location)?;
+                    text = rest.trim();
+                }
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = match text.split_once(char::is_whitespace) {
+                Some((word, rest)) => (word, rest.trim()),
+                None => (text, ""),
+            };
+            let keyword = word.to_ascii_lowercase();
+
+            // Constants: NAME = expr or NAME EQU expr
+            let constant = match rest.split_once(char::is_whitespace) {
+                Some((equ, value)) if equ.eq_ignore_ascii_case("equ") => Some(value.trim()),
+                _ => rest.strip_prefix('=').map(str::trim),
+            };
+            if let (Some(value), true) = (constant, is_identifier(word)) {
+                self.define_constant(word, value, &location)?;
+                continue;
+            }
+            if let Some((name, value)) = text.split_once('=') {
+                if is_identifier(name.trim()) {
+                    self.define_constant(name.trim(), value.trim(), &location)?;
+                    continue;
+                }
+            }
+
+            let (body, size) = match keyword.as_str() {
+                "include" => {
+                    let name = rest.trim_matches('"');
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(location.error(format!("includes are nested too deeply at {name}")));
+                    }
+                    let path: PathBuf = dir.join(name);
+                    self.read_file(&path, depth + 1)?;
+                    continue;
+                }
+                "db" => {
+                    let values = split_operands(rest);
+                    let size = values.len() as u32;
+                    (Body::Bytes(values), size)
+                }
+                "dw" => {
+                    let values = split_operands(rest);
+                    let size = 2 * values.len() as u32;
+                    (Body::Words(values), size)
+                }
+                _ => {
+                    let operands = split_operands(rest);
+                    let long = operands
+                        .iter()
+                        .any(|operand| matches!(parse_operand(operand), Operand::Long(_)));
+                    (Body::Instruction(keyword, operands), if long { 4 } else { 2 })
+                }
+            };
+
+            if self.addr + size > 0x10000 {
+                return Err(location.error("program does not fit in memory"));
+            }
+            self.statements.push(Statement {
+                location,
+                addr: self.addr as u16,
+                body,
+            });
+            self.addr += size;
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str, location: &Location) -> Result<()> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(location.error(format!("{name} is defined more than once")));
+        }
+        self.labels.insert(name.to_string(), self.addr as u16);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &str, value: &str, location: &Location) -> Result<()> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(location.error(format!("{name} is defined more than once")));
+        }
+        self.constants
+            .insert(name.to_string(), (value.to_string(), location.clone()));
+        Ok(())
+    }
+
+    // Second pass: evaluate every expression and emit the bytes
+    fn finish(self) -> Result<Program> {
+        let mut bytes = Vec::new();
+        for statement in &self.statements {
+            debug_assert_eq!(usize::from(statement.addr - PROGRAM_START), bytes.len());
+            let location = &statement.location;
+            match &statement.body {
+                Body::Bytes(values) => {
+                    for value in values {
+                        let value = self.evaluate(value, location, 0)?;
+                        if !(-128..=0xFF).contains(&value) {
+                            return Err(location.error(format!("{value} does not fit in a byte")));
+                        }
+                        bytes.push(value as u8);
+                    }
+                }
+                Body::Words(values) => {
+                    for value in values {
+                        let value = self.evaluate(value, location, 0)?;
+                        if !(-0x8000..=0xFFFF).contains(&value) {
+                            return Err(location.error(format!("{value} does not fit in a word")));
+                        }
+                        bytes.extend((value as u16).to_be_bytes());
+                    }
+                }
+                Body::Instruction(mnemonic, operands) => {
+                    let operands: Vec<Operand> =
+                        operands.iter().map(|operand| parse_operand(operand)).collect();
+                    let (opcode, long) = self.encode(mnemonic, &operands, location)?;
+                    let (byte_1, byte_2) = opcode.encode();
+                    bytes.extend([byte_1, byte_2]);
+                    if let Some(addr) = long {
+                        bytes.extend(addr.to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(Program {
+            bytes,
+            symbols: self.labels,
+        })
+    }
+
+    fn evaluate(&self, expr: &str, location: &Location, depth: usize) -> Result<i64> {
+        if depth > self.constants.len() {
+            return Err(location.error(format!("constant {expr} refers to itself")));
+        }
+        let expr = expr.trim();
+        if expr.is_empty() {
+            return Err(location.error("missing value"));
+        }
+
+        // Split into terms on + and -, keeping a leading sign with its term
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut term = String::new();
+        let mut terms = Vec::new();
+        for c in expr.chars() {
+            if (c == '+' || c == '-') && !term.trim().is_empty() {
+                terms.push((sign, std::mem::take(&mut term)));
+                sign = if c == '-' { -1 } else { 1 };
+            } else if (c == '+' || c == '-') && term.trim().is_empty() {
+                sign *= if c == '-' { -1 } else { 1 };
+            } else {
+                term.push(c);
+            }
+        }
+        terms.push((sign, term));
+
+        for (sign, term) in terms {
+            let term = term.trim();
+            let value = if let Some(addr) = self.labels.get(term) {
+                i64::from(*addr)
+            } else if let Some((value, _)) = self.constants.get(term) {
+                self.evaluate(value, location, depth + 1)?
+            } else {
+                parse_number(term).ok_or_else(|| {
+                    if is_identifier(term) {
+                        location.error(format!("{term} is not defined"))
+                    } else {
+                        location.error(format!("{term} is not a number"))
+                    }
+                })?
+            };
+            total += sign * value;
+        }
+        Ok(total)
+    }
+
+    fn value_in(&self, expr: &str, max: i64, location: &Location) -> Result<i64> {
+        let value = self.evaluate(expr, location, 0)?;
+        if !(0..=max).contains(&value) {
+            return Err(location.error(format!("{value} is out of range 0..={max}")));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, expr: &str, location: &Location) -> Result<u8> {
+        let value = self.evaluate(expr, location, 0)?;
+        if !(-128..=0xFF).contains(&value) {
+            return Err(location.error(format!("{value} does not fit in a byte")));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&self, expr: &str, location: &Location) -> Result<u4> {
+        Ok(u4::new(self.value_in(expr, 0xF, location)? as u8))
+    }
+
+    fn addr(&self, expr: &str, location: &Location) -> Result<u12> {
+        Ok(u12::new(self.value_in(expr, 0xFFF, location)? as u16))
+    }
+
+    /* Encodes one instruction, returning the address that follows it for LD I, LONG */
+    fn encode(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        location: &Location,
+    ) -> Result<(Opcode, Option<u16>)> {
+        use Operand::*;
+        let v = |register: &u8| u4::new(*register);
+
+        let opcode = match (mnemonic, operands) {
+            ("cls", []) => Opcode::ClearScreen,
+            ("ret", []) => Opcode::Return,
+            ("exit", []) => Opcode::Exit,
+            ("low", []) => Opcode::LowRes,
+            ("high", []) => Opcode::HighRes,
+            ("scr", []) => Opcode::ScrollRight,
+            ("scl", []) => Opcode::ScrollLeft,
+            ("scd", [Expr(n)]) => Opcode::ScrollDown(self.nibble(n, location)?),
+            ("audio", []) => Opcode::AudioPattern,
+            ("plane", [Expr(n)]) => Opcode::Plane(self.nibble(n, location)?),
+            ("pitch", [V(x)]) => Opcode::Pitch(v(x)),
+            ("jp", [Expr(addr)]) => Opcode::Jump(self.addr(addr, location)?),
+            ("jp", [V(0), Expr(addr)]) => Opcode::JumpOffset(self.addr(addr, location)?),
+            ("call", [Expr(addr)]) => Opcode::CallSubroutine(self.addr(addr, location)?),
+            ("se", [V(x), V(y)]) => Opcode::SkipEqReg(v(x), v(y)),
+            ("se", [V(x), Expr(nn)]) => Opcode::SkipEqImm(v(x), self.byte(nn, location)?),
+            ("sne", [V(x), V(y)]) => Opcode::SkipNeqReg(v(x), v(y)),
+            ("sne", [V(x), Expr(nn)]) => Opcode::SkipNeqImm(v(x), self.byte(nn, location)?),
+            ("ld", [V(x), V(y)]) => Opcode::Set(v(x), v(y)),
+            ("ld", [V(x), Expr(nn)]) => Opcode::SetReg(v(x), self.byte(nn, location)?),
+            ("ld", [I, Expr(addr)]) => Opcode::SetI(self.addr(addr, location)?),
+            ("ld", [I, Long(addr)]) => {
+                let addr = self.value_in(addr, 0xFFFF, location)? as u16;
+                return Ok((Opcode::LongSetI, Some(addr)));
+            }
+            ("ld", [V(x), DT]) => Opcode::SetRegToDelay(v(x)),
+            ("ld", [V(x), K]) => Opcode::GetKey(v(x)),
+            ("ld", [DT, V(x)]) => Opcode::SetDelayToReg(v(x)),
+            ("ld", [ST, V(x)]) => Opcode::SetSoundToReg(v(x)),
+            ("ld", [F, V(x)]) => Opcode::Font(v(x)),
+            ("ld", [HF, V(x)]) => Opcode::BigFont(v(x)),
+            ("ld", [B, V(x)]) => Opcode::Decimal(v(x)),
+            ("ld", [IndirectI, V(x)]) => Opcode::Store(v(x)),
+            ("ld", [V(x), IndirectI]) => Opcode::Load(v(x)),
+            ("ld", [R, V(x)]) => Opcode::SaveFlags(v(x)),
+            ("ld", [V(x), R]) => Opcode::LoadFlags(v(x)),
+            ("add", [V(x), V(y)]) => Opcode::Add(v(x), v(y)),
+            ("add", [V(x), Expr(nn)]) => Opcode::AddReg(v(x), self.byte(nn, location)?),
+            ("add", [I, V(x)]) => Opcode::AddToIndex(v(x)),
+            ("or", [V(x), V(y)]) => Opcode::Or(v(x), v(y)),
+            ("and", [V(x), V(y)]) => Opcode::And(v(x), v(y)),
+            ("xor", [V(x), V(y)]) => Opcode::Xor(v(x), v(y)),
+            ("sub", [V(x), V(y)]) => Opcode::Subtract1(v(x), v(y)),
+            ("subn", [V(x), V(y)]) => Opcode::Subtract2(v(x), v(y)),
+            // Without VY the register is shifted in place, whichever shift quirk is in use
+            ("shr", [V(x)]) => Opcode::ShiftR(v(x), v(x)),
+            ("shr", [V(x), V(y)]) => Opcode::ShiftR(v(x), v(y)),
+            ("shl", [V(x)]) => Opcode::ShiftL(v(x), v(x)),
+            ("shl", [V(x), V(y)]) => Opcode::ShiftL(v(x), v(y)),
+            ("rnd", [V(x), Expr(nn)]) => Opcode::Random(v(x), self.byte(nn, location)?),
+            ("drw", [V(x), V(y), Expr(n)]) => Opcode::Display(v(x), v(y), self.nibble(n, location)?),
+            ("skp", [V(x)]) => Opcode::SkipIfKey(v(x)),
+            ("sknp", [V(x)]) => Opcode::SkipIfNotKey(v(x)),
+            ("save", [V(x), V(y)]) => Opcode::SaveRange(v(x), v(y)),
+            ("load", [V(x), V(y)]) => Opcode::LoadRange(v(x), v(y)),
+            _ => {
+                return Err(location.error(format!(
+                    "{} does not take the operands {:?}",
+                    mnemonic.to_ascii_uppercase(),
+                    operands
+                )))
+            }
+        };
+        Ok((opcode, None))
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+pub mod assembler_tests {
+    use super::*;
+    use crate::disasm::{Disassembly, Options};
+
+    #[test]
+    pub fn test_assemble() {
+        let source = "
+            SPEED = 3
+            HEIGHT EQU 5
+        start:
+            cls
+            ld i, glyph        ; point at the sprite
+            ld v0, SPEED + 1
+            drw v0, v1, HEIGHT
+            call sub
+            jp start
+        sub: ret
+        glyph:
+            db 0xF0, $90, 0b10010000, 144, 240
+            dw glyph - 2
+        ";
+        let program = Assembler::assemble(source).unwrap();
+        assert_eq!(
+            program.bytes,
+            vec![
+                0x00, 0xE0, 0xA2, 0x0E, 0x60, 0x04, 0xD0, 0x15, 0x22, 0x0C, 0x12, 0x00, 0x00,
+                0xEE, 0xF0, 0x90, 0x90, 0x90, 0xF0, 0x02, 0x0C,
+            ]
+        );
+        assert_eq!(program.symbols["sub"], 0x20C);
+        assert_eq!(program.symbol_file(), "0x200 start\n0x20C sub\n0x20E glyph\n");
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let line = |source: &str| match Assembler::assemble(source) {
+            Err(Chip8Error::AssemblyError { line, .. }) => line,
+            other => panic!("expected an assembly error, got {:?}", other),
+        };
+        assert_eq!(line("cls\nld v0, 256"), 2);
+        assert_eq!(line("jp nowhere"), 1);
+        assert_eq!(line("a:\na:"), 2);
+        assert_eq!(line("drw v0, 5"), 1);
+        assert_eq!(line("A = B\nB = A\nld v0, A"), 3);
+    }
+
+    #[test]
+    pub fn test_disassembly_round_trip() {
+        // Includes a jump into the middle of the long index load
+        let rom = [
+            0x22, 0x08, 0xA2, 0x12, 0x12, 0x0C, 0xFF, 0xFF, 0x80, 0x16, 0x00, 0xEE, 0xF0, 0x00,
+            0x02, 0x12, 0x00, 0xFD, 0x3C, 0x42, 0x12, 0x0E,
+        ];
+        let listing = Disassembly::new(&rom).listing(Options::default());
+        assert_eq!(Assembler::assemble(&listing).unwrap().bytes, rom);
+    }
+}
@@ -0,0 +1,196 @@
+//! Tone generation for the sound timer, independent of any audio backend.
+//!
+//! A ``Tone`` produces mono ``f32`` samples. Whether it sounds is updated once per frame from
+//! the sound timer, and the volume ramps over a couple of milliseconds whenever it starts or
+//! stops, so the tone never clicks, and its phase carries on across frames so consecutive
+//! frames of sound join without gaps.
+//!
+//! Once an XO-CHIP program loads a non-silent audio pattern, the pattern is played instead of
+//! the tone, at the rate set by its pitch register.
+
+use crate::cpu::{CPU, DEFAULT_PITCH};
+use crate::error::*;
+use std::f32::consts::TAU;
+use std::str::FromStr;
+
+// Sample rate requested from audio backends
+pub const SAMPLE_RATE: u32 = 44100;
+// Length of the fade when the tone starts or stops, in seconds
+const RAMP_SECONDS: f32 = 0.002;
+// Bits in an XO-CHIP audio pattern
+const PATTERN_BITS: f32 = 128.0;
+
+/* Returns the rate in bits per second at which XO-CHIP plays audio patterns for ``pitch`` */
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((f32::from(pitch) - f32::from(DEFAULT_PITCH)) / 48.0)
+}
+
+/// The shape of one period of the tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl FromStr for Waveform {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<Waveform> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" | "saw" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(Chip8Error::CommandParseError(format!("unknown waveform {s}"))),
+        }
+    }
+}
+
+impl Waveform {
+    /* Returns the value of the waveform, from -1 to 1, at ``phase`` from 0 to 1 through a period */
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+        }
+    }
+}
+
+/// How the sound timer's tone sounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneSettings {
+    pub pitch: f32,  // frequency in Hz
+    pub volume: f32, // amplitude from 0 to 1
+    pub waveform: Waveform,
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        ToneSettings {
+            pitch: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+/// Generates the tone sounded while the sound timer is non-zero
+#[derive(Debug, Clone)]
+pub struct Tone {
+    settings: ToneSettings,
+    sample_rate: u32,
+    phase: f32, // position within the current period or pattern, from 0 to 1
+    gain: f32,  // current amplitude, moving towards the volume or 0
+    active: bool,
+    muted: bool,
+    pattern: Option<([u8; 16], u8)>, // XO-CHIP audio pattern and pitch, played instead of the tone
+}
+
+impl Tone {
+    pub fn new(settings: ToneSettings, sample_rate: u32) -> Self {
+        Tone {
+            settings,
+            sample_rate,
+            phase: 0.0,
+            gain: 0.0,
+            active: false,
+            muted: false,
+            pattern: None,
+        }
+    }
+
+    /* Follows the audio pattern of ``cpu``, sounding if ``sounding`` is set. Called at the end
+    of every frame with whether the sound timer was running during it */
+    pub fn update(&mut self, cpu: &CPU, sounding: bool) {
+        self.set_active(sounding);
+        let pattern = cpu.audio_pattern();
+        let pattern = Some((pattern, cpu.pitch())).filter(|_| pattern != [0; 16]);
+        if pattern.is_some() != self.pattern.is_some() {
+            self.phase = 0.0;
+        }
+        self.pattern = pattern;
+    }
+
+    /* Starts or stops the tone */
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /* Fills ``out`` with the next samples */
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let target = if self.active && !self.muted { self.settings.volume } else { 0.0 };
+        let ramp_step = self.settings.volume / (RAMP_SECONDS * self.sample_rate as f32);
+        let phase_step = match self.pattern {
+            Some((_, pitch)) => pattern_rate(pitch) / PATTERN_BITS / self.sample_rate as f32,
+            None => self.settings.pitch / self.sample_rate as f32,
+        };
+        for sample in out.iter_mut() {
+            if self.gain < target {
+                self.gain = (self.gain + ramp_step).min(target);
+            } else if self.gain > target {
+                self.gain = (self.gain - ramp_step).max(target);
+            }
+            let value = match &self.pattern {
+                Some((pattern, _)) => {
+                    let bit = ((self.phase * PATTERN_BITS) as usize).min(127);
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                None => self.settings.waveform.sample(self.phase),
+            };
+            *sample = value * self.gain;
+            self.phase = (self.phase + phase_step).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod audio_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_tone_ramps() {
+        let settings = ToneSettings {
+            pitch: 1000.0,
+            volume: 0.5,
+            waveform: Waveform::Square,
+        };
+        let mut tone = Tone::new(settings, 8000);
+        let mut samples = [1.0; 64];
+        tone.fill(&mut samples);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+
+        tone.set_active(true);
+        tone.fill(&mut samples);
+        // The fade in lasts 16 samples, then the square wave has a period of 8 samples
+        assert!(samples[0].abs() < 0.5);
+        assert_eq!(&samples[32..40], &[0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+
+        tone.set_muted(true);
+        tone.fill(&mut samples);
+        assert!(samples[..16].iter().any(|sample| *sample != 0.0));
+        assert!(samples[16..].iter().all(|sample| *sample == 0.0));
+    }
+}
@@ -0,0 +1,62 @@
+use chip8_crab::assembler::Assembler;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: asm <SOURCE> [--output <FILE>] [--symbols <FILE>]
+
+Assembles CHIP-8 mnemonics into a ROM.
+
+Options:
+  --output <FILE>    where to write the ROM (default: SOURCE with a .ch8 extension)
+  --symbols <FILE>   also write the address of every label to FILE";
+
+fn main() -> ExitCode {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = args.next(),
+            "--symbols" => symbols = args.next(),
+            _ if !arg.starts_with("--") && source.is_none() => source = Some(arg),
+            _ => {
+                eprintln!("Unexpected argument: {}", arg);
+                eprintln!("{}", USAGE);
+                return ExitCode::from(1);
+            }
+        }
+    }
+    let Some(source) = source else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(1);
+    };
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&source)
+            .with_extension("ch8")
+            .display()
+            .to_string()
+    });
+
+    let program = match Assembler::assemble_file(&source) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::from(1);
+        }
+    };
+
+    if let Err(err) = std::fs::write(&output, &program.bytes) {
+        eprintln!("Error: could not write {}: {}", output, err);
+        return ExitCode::from(1);
+    }
+    if let Some(path) = symbols {
+        if let Err(err) = std::fs::write(&path, program.symbol_file()) {
+            eprintln!("Error: could not write {}: {}", path, err);
+            return ExitCode::from(1);
+        }
+    }
+    println!("Assembled {} bytes into {}", program.bytes.len(), output);
+    ExitCode::SUCCESS
+}
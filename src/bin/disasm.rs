@@ -0,0 +1,105 @@
+use chip8_crab::disasm::{Disassembly, Options};
+use chip8_crab::error::*;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: disasm <ROM> [--sprites] [--syntax <SYNTAX>] [--output <FILE>]
+
+Disassembles a ROM by following its control flow from 0x200.
+
+Options:
+  --sprites          draw data bytes as sprite rows in comments
+  --syntax <SYNTAX>  cowgod or octo (default cowgod)
+  --output <FILE>    write the listing to a file instead of standard output";
+
+/// Settings for a disassembly, read from the command line
+#[derive(Debug)]
+struct Args {
+    rom: String,
+    options: Options,
+    output: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut rom = None;
+    let mut options = Options::default();
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "--sprites" {
+            options.sprite_art = true;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            if rom.replace(arg.clone()).is_some() {
+                return Err(Chip8Error::CommandParseError(format!("unexpected argument {arg}")));
+            }
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| Chip8Error::CommandParseError(format!("{arg} needs a value")))?;
+        match arg.as_str() {
+            "--syntax" => options.syntax = value.parse()?,
+            "--output" => output = Some(value),
+            _ => return Err(Chip8Error::CommandParseError(arg)),
+        }
+    }
+
+    let rom = rom.ok_or_else(|| Chip8Error::CommandParseError("no ROM given".to_string()))?;
+    Ok(Args { rom, options, output })
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            eprintln!("{}", USAGE);
+            return ExitCode::from(1);
+        }
+    };
+
+    let bytes = match std::fs::read(&args.rom) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Error: could not read {}: {}", args.rom, err);
+            return ExitCode::from(1);
+        }
+    };
+    let listing = Disassembly::new(&bytes).listing(args.options);
+
+    match args.output {
+        Some(path) => {
+            if let Err(err) = std::fs::write(&path, listing) {
+                eprintln!("Error: could not write {}: {}", path, err);
+                return ExitCode::from(1);
+            }
+        }
+        None => print!("{}", listing),
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+pub mod disasm_tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    pub fn test_parse_args() {
+        let args = parse(&["pong.ch8", "--sprites", "--output", "pong.asm"]).unwrap();
+        assert_eq!(args.rom, "pong.ch8");
+        assert!(args.options.sprite_art);
+        assert_eq!(args.output.as_deref(), Some("pong.asm"));
+
+        assert!(parse(&["pong.ch8", "--output"]).is_err());
+        assert!(parse(&["pong.ch8", "--syntax"]).is_err());
+        assert!(parse(&["pong.ch8", "--syntax", "intel"]).is_err());
+        assert!(parse(&["pong.ch8", "other.ch8"]).is_err());
+        assert!(parse(&["--sprites"]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use chip8_crab::cpu::CPU;
+use chip8_crab::error::*;
+use chip8_crab::audio::ToneSettings;
+use chip8_crab::frontend::{Exit, Frontend, NullDisplay};
+use chip8_crab::image;
+use chip8_crab::loader::load_program_with_quirks;
+use chip8_crab::machine::{Machine, DEFAULT_IPS};
+use chip8_crab::quirks::Platform;
+use chip8_crab::script::KeyScript;
+use chip8_crab::wav::WavSink;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: headless <ROM> [options]
+
+Runs a ROM without a window and prints a summary of the final CPU state.
+
+Options:
+  --frames <N>       number of 60Hz frames to run (default 60)
+  --ips <N>          instructions per second (default 700)
+  --quirks <PRESET>  chip8, chip48, schip or xochip (default schip)
+  --keys <SCRIPT>    key script such as 0:5,30:-; prefix with @ to read it from a file
+  --seed <N>         seed for the random number generator (default 0)
+  --output <FILE>    write the final display to a .png or .pbm file
+  --audio <FILE>     record the sound of the run to a .wav file";
+
+/// Settings for a headless run, read from the command line
+struct Options {
+    rom: String,
+    frames: u64,
+    ips: u32,
+    platform: Platform,
+    keys: KeyScript,
+    seed: u64,
+    output: Option<String>,
+    audio: Option<String>,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Chip8Error::NumericalConversionError(format!("{flag} {value}")))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        frames: 60,
+        ips: DEFAULT_IPS,
+        platform: Platform::SuperChip,
+        keys: KeyScript::default(),
+        seed: 0,
+        output: None,
+        audio: None,
+    };
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.replace(arg.clone()).is_some() {
+                return Err(Chip8Error::CommandParseError(format!("unexpected argument {arg}")));
+            }
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| Chip8Error::CommandParseError(format!("{arg} needs a value")))?;
+        match arg.as_str() {
+            "--frames" => options.frames = parse_number(&arg, &value)?,
+            "--ips" => options.ips = parse_number(&arg, &value)?,
+            "--quirks" => options.platform = value.parse()?,
+            "--keys" => {
+                let script = match value.strip_prefix('@') {
+                    Some(path) => std::fs::read_to_string(path).map_err(|err| {
+                        Chip8Error::KeyScriptError(format!("could not read {path}: {err}"))
+                    })?,
+                    None => value,
+                };
+                options.keys = KeyScript::parse(&script)?;
+            }
+            "--seed" => options.seed = parse_number(&arg, &value)?,
+            "--output" => options.output = Some(value),
+            "--audio" => options.audio = Some(value),
+            _ => return Err(Chip8Error::CommandParseError(arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| Chip8Error::CommandParseError("no ROM given".to_string()))?;
+    Ok(options)
+}
+
+fn print_summary(cpu: &CPU, frames: u64, outcome: &str) {
+    println!("frames:  {}", frames);
+    println!("outcome: {}", outcome);
+    println!("pc:      {:#05X}", cpu.program_counter());
+    println!("I:       {:#05X}", cpu.index());
+    for (row, registers) in cpu.vs.chunks(8).enumerate() {
+        let registers: Vec<String> = registers.iter().map(|v| format!("{:02X}", v)).collect();
+        println!("V{:X}-V{:X}:   {}", row * 8, row * 8 + 7, registers.join(" "));
+    }
+    println!("delay:   {}", cpu.delay);
+    println!("sound:   {}", cpu.beep);
+    println!("display: {}x{}", cpu.width(), cpu.height());
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            eprintln!("{}", USAGE);
+            return ExitCode::from(1);
+        }
+    };
+
+    let mut cpu = match load_program_with_quirks(&options.rom, options.platform.quirks()) {
+        Ok(cpu) => cpu,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::from(1);
+        }
+    };
+    cpu.set_seed(options.seed);
+
+    let mut machine = Machine::with_ips(cpu, options.ips);
+    // Samples are only kept when they will be written, as long runs would buffer a lot of them
+    let recorder = options.audio.is_some().then(|| WavSink::new(ToneSettings::default()));
+    let mut frontend = Frontend::new(NullDisplay, recorder, options.keys);
+    let mut outcome = "completed".to_string();
+    let mut code = ExitCode::SUCCESS;
+    while frontend.input.frame() < options.frames {
+        match frontend.frame(&mut machine) {
+            Ok(None) => {}
+            Ok(Some(Exit::Halted)) | Ok(Some(Exit::Quit)) => {
+                outcome = "halted".to_string();
+                break;
+            }
+            Err(err) => {
+                outcome = format!("faulted: {}", err);
+                code = ExitCode::from(2);
+                break;
+            }
+        }
+    }
+    print_summary(machine.cpu(), frontend.input.frame(), &outcome);
+
+    if let Some(output) = &options.output {
+        if let Err(err) = image::save(machine.cpu(), output) {
+            eprintln!("Error: {}", err);
+            return ExitCode::from(1);
+        }
+    }
+    if let (Some(audio), Some(recorder)) = (&options.audio, &frontend.audio) {
+        if let Err(err) = recorder.save(audio) {
+            eprintln!("Error: {}", err);
+            return ExitCode::from(1);
+        }
+    }
+    code
+}
@@ -1,14 +1,177 @@
-use chip8_crab::input::run;
-use ctrlc;
-
-fn main() {
-    // TODO: convert demo into valid input for emulator
-    let codes = run();
-    loop {
-        if codes.lock().unwrap().len() > 0 {
-            println!("{:?}", codes.lock().unwrap());
-            codes.lock().unwrap().clear();
-        }
-        std::thread::sleep(std::time::Duration::from_millis(100));
+use chip8_crab::palette::Palette;
+use chip8_crab::error::*;
+use chip8_crab::frontend::Status;
+use chip8_crab::input::{run, WindowOptions};
+use chip8_crab::keymap::Keymap;
+use chip8_crab::loader::load_program_with_quirks;
+use chip8_crab::machine::{Machine, DEFAULT_IPS};
+use chip8_crab::quirks::Platform;
+use chip8_crab::rewind::DEFAULT_REWIND_FRAMES;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: main <ROM> [options]
+
+Runs a ROM in a window. Escape quits, F5 saves a state next to the ROM, F9 loads it,
+holding Backspace rewinds, M mutes the sound and P switches to the next palette.
+
+Options:
+  --scale <N>          window pixels per CHIP-8 pixel (default 20)
+  --ips <N>            instructions per second (default 700)
+  --quirks <PRESET>    chip8, chip48, schip or xochip (default schip)
+  --palette <PALETTE>  classic, amber, green, lcd or high-contrast, or comma separated
+                       RRGGBB background and foreground colours, optionally followed by
+                       the colours of the second plane and of both planes
+  --keymap <FILE>      keypad mapping file, see below
+  --pitch <HZ>         frequency of the sound timer's tone (default 440)
+  --volume <LEVEL>     volume of the tone from 0 to 1 (default 0.25)
+  --waveform <SHAPE>   square, triangle, sawtooth or sine (default square)
+  --mute               start with the sound muted
+  --phosphor <DECAY>   fade pixels out like a CRT to hide flicker, keeping DECAY (0 to 1)
+                       of their brightness each frame; 0.5 works well
+  --filter <FILTER>    upscaler: nearest, scale2x, scale3x or epx (default nearest)
+  --overlay <OVERLAY>  none, scanlines or grid (default none)
+  --scale-mode <MODE>  fit to scale to the window, or integer to keep every pixel the
+                       same size (default fit)
+  --fullscreen         start in fullscreen
+  --seed <N>           seed for the random number generator (default: random)
+
+A keymap file binds keypad keys to SDL key names and controller inputs, one
+`KEY = NAME, NAME` line per key, for example `5 = Up, Keypad 8, Pad:dpup, Pad:lefty-`.
+`deadzone = N` sets how far sticks must move to press a key. Lines after a
+`[ROM file name]` header only apply to that ROM. Unmapped keys keep the default layout,
+and a line only replaces the keyboard or controller bindings of its key, whichever it names.
+Key names are scancodes: they name where a key sits on a US QWERTY keyboard, so the
+default layout needs no remapping on AZERTY or other layouts.";
+
+/// Settings for the emulator, read from the command line
+struct Options {
+    rom: String,
+    ips: u32,
+    platform: Platform,
+    seed: Option<u64>,
+    keymap: Option<String>,
+    window: WindowOptions,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Chip8Error::NumericalConversionError(format!("{flag} {value}")))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        ips: DEFAULT_IPS,
+        platform: Platform::SuperChip,
+        seed: None,
+        keymap: None,
+        window: WindowOptions::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.replace(arg.clone()).is_some() {
+                return Err(Chip8Error::CommandParseError(format!("unexpected argument {arg}")));
+            }
+            continue;
+        }
+        match arg.as_str() {
+            "--fullscreen" => {
+                options.window.fullscreen = true;
+                continue;
+            }
+            "--mute" => {
+                options.window.muted = true;
+                continue;
+            }
+            _ => {}
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| Chip8Error::CommandParseError(format!("{arg} needs a value")))?;
+        match arg.as_str() {
+            "--scale" => options.window.scale = parse_number(&arg, &value)?,
+            "--ips" => options.ips = parse_number(&arg, &value)?,
+            "--quirks" => options.platform = value.parse()?,
+            "--palette" => options.window.palette = value.parse::<Palette>()?,
+            "--pitch" => options.window.tone.pitch = parse_number(&arg, &value)?,
+            "--volume" => options.window.tone.volume = parse_number(&arg, &value)?,
+            "--waveform" => options.window.tone.waveform = value.parse()?,
+            "--phosphor" => options.window.phosphor = Some(parse_number(&arg, &value)?),
+            "--filter" => options.window.scaling.filter = value.parse()?,
+            "--overlay" => options.window.scaling.overlay = value.parse()?,
+            "--scale-mode" => options.window.scaling.mode = value.parse()?,
+            "--keymap" => options.keymap = Some(value),
+            "--seed" => options.seed = Some(parse_number(&arg, &value)?),
+            _ => return Err(Chip8Error::CommandParseError(arg)),
+        }
+    }
+
+    let tone = options.window.tone;
+    if !(tone.pitch > 0.0 && (0.0..=1.0).contains(&tone.volume)) {
+        return Err(Chip8Error::NumericalConversionError(
+            "--pitch must be positive and --volume between 0 and 1".to_string(),
+        ));
+    }
+    if options.window.phosphor.is_some_and(|decay| !(0.0..1.0).contains(&decay)) {
+        return Err(Chip8Error::NumericalConversionError(
+            "--phosphor must be at least 0 and below 1".to_string(),
+        ));
+    }
+    if options.window.scale == 0 {
+        return Err(Chip8Error::NumericalConversionError("--scale must be at least 1".to_string()));
+    }
+    options.rom = rom.ok_or_else(|| Chip8Error::CommandParseError("no ROM given".to_string()))?;
+    if let Some(path) = &options.keymap {
+        options.window.keymap = Keymap::load(path, Some(&options.rom))?;
+    }
+    options.window.state_path = Some(format!("{}.state", options.rom));
+    Ok(options)
+}
+
+// Errors go to stderr, everything else to stdout
+fn print_status(status: Status) {
+    match status {
+        Status::Error(_) => eprintln!("{}", status),
+        _ => println!("{}", status),
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            eprintln!("{}", USAGE);
+            return ExitCode::from(1);
+        }
+    };
+
+    let mut cpu = match load_program_with_quirks(&options.rom, options.platform.quirks()) {
+        Ok(cpu) => cpu,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::from(1);
+        }
+    };
+    if let Some(seed) = options.seed {
+        cpu.set_seed(seed);
+    }
+
+    let mut machine = Machine::with_ips(cpu, options.ips);
+    machine.enable_rewind(DEFAULT_REWIND_FRAMES);
+    match run(&mut machine, &options.window, print_status) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err @ Chip8Error::RuntimeError { .. }) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(3)
+        }
     }
 }
@@ -1,9 +1,11 @@
 use chip8_crab::cpu::*;
 use chip8_crab::error::*;
 use chip8_crab::loader;
+use chip8_crab::machine::Machine;
+use chip8_crab::memory::Memory;
+use chip8_crab::syntax::Syntax;
 use regex::Regex;
 use std::sync::{Arc, Mutex};
-use ux::*;
 
 fn parse_command(command: &str) -> Result<(Command, String)> {
     let cap = Regex::new(r"(\w+)(.*)").unwrap().captures(command).unwrap();
@@ -24,6 +26,8 @@ fn parse_command(command: &str) -> Result<(Command, String)> {
         "v" | "vi" | "vie" | "view" => Ok((Command::View, rest)),
         "b" | "br" | "bre" | "brea" | "break" | "breakpoint" => Ok((Command::Breakpoint, rest)),
         "m" | "me" | "mem" | "memory" => Ok((Command::Memory, rest)),
+        "t" | "tr" | "tra" | "trac" | "trace" => Ok((Command::Trace, rest)),
+        "sy" | "syn" | "synt" | "synta" | "syntax" => Ok((Command::Syntax, rest)),
         _ => Err(Chip8Error::CommandParseError(command.to_string())),
     }
 }
@@ -46,6 +50,28 @@ fn parse_hex(input: &str) -> Result<u16> {
     Ok(hex)
 }
 
+/// Prints the word at ``addr``, or "____" if it is out of range
+fn print_word(ram: &Memory, addr: u16) {
+    match ram.read_word(addr) {
+        Ok(line) => print!("{:04X} ", line),
+        Err(_) => print!("____ "),
+    }
+}
+
+/// Returns the instruction at the pc, decoded and formatted in ``syntax``
+fn describe_instruction(cpu: &CPU, syntax: Syntax) -> String {
+    let pc = cpu.program_counter();
+    let instr_hex = match cpu.ram().read_word(pc) {
+        Ok(instr_hex) => instr_hex,
+        Err(fault) => return format!("0x{:04X}: {}", pc, fault),
+    };
+    let instr = ((instr_hex >> 8) as u8, instr_hex as u8);
+    match cpu.try_decode(instr) {
+        Ok(opcode) => format!("0x{:04X}: {:04X} | {}", pc, instr_hex, opcode.format(syntax)),
+        Err(_) => format!("0x{:04X}: {:04X} | INVALID_OPCODE", pc, instr_hex),
+    }
+}
+
 #[derive(Debug, PartialEq)]
 enum Command {
     /// Load a ROM into the CPU but do not yet execute it
@@ -67,11 +93,17 @@ enum Command {
     Breakpoint,
     /// View the memory of the CPU
     Memory,
+    /// Toggles printing every instruction as it is executed
+    Trace,
+    /// Selects the mnemonic syntax instructions are printed in (cowgod or octo)
+    Syntax,
 }
 
 fn main() {
-    let mut cpu = CPU::new();
+    let mut machine = Machine::new(CPU::new());
     let mut breakpoints = Vec::new();
+    let mut tracing = false;
+    let mut syntax = Syntax::default();
     let terminate = Arc::new(Mutex::new(0));
     let terminate_clone = terminate.clone();
 
@@ -112,42 +144,38 @@ fn main() {
         match command {
             Command::Memory => {
                 // Prints around the memory, a little above and below
-                let pc = cpu.program_counter();
-                let pc = pc;
+                let pc = machine.cpu().program_counter();
+                let size = machine.cpu().ram().size();
                 let addr;
                 if rest.trim().len() > 0 {
                     if parse_hex(&rest).is_err() {
                         println!("Could not parse memory address: {}", rest);
                         continue;
                     }
-                    addr = match u12::try_from(parse_hex(&rest).unwrap()) {
-                        Ok(addr) => addr,
-                        Err(_) => {
-                            println!("Could not convert to u12: {}", rest);
-                            continue;
-                        }
+                    addr = parse_hex(&rest).unwrap();
+                    if usize::from(addr) >= size {
+                        println!("Address out of range of {} bytes of memory: {}", size, rest);
+                        continue;
                     }
                 } else {
-                    addr = u12::try_from(pc).expect("pc should never be larger than u12");
+                    addr = pc;
                 }
                 // Round down to nearest 16 bytes
-                let addr = addr & u12::try_from(0xFF0 as u16).unwrap();
+                let addr = addr & 0xFFF0;
                 // Print 8 groups of 2 bytes per row, or "____" if out of range
                 // for three rows above and below the address
 
                 // row above
-                if addr >= u12::from(16) {
-                    print!("0x{:04X}: ", u16::from(addr) - 16);
+                if addr >= 16 {
+                    print!("0x{:04X}: ", addr - 16);
                 } else {
                     print!("____ :");
                 }
                 for i in 0..8 {
-                    if addr < u12::from(16) {
+                    if addr < 16 {
                         print!("____ ");
                     } else {
-                        let ram = cpu.ram();
-                        let line = ram.read_word(addr - u12::from(16) + u12::from(i * 2));
-                        print!("{:04X} ", line)
+                        print_word(machine.cpu().ram(), addr - 16 + i * 2);
                     }
                 }
                 println!();
@@ -155,21 +183,17 @@ fn main() {
                 // row around
                 print!("0x{:04X}: ", addr);
                 for i in 0..8 {
-                    let ram = cpu.ram();
-                    let line = ram.read_word(addr + u12::from(i * 2));
-                    print!("{:04X} ", line)
+                    print_word(machine.cpu().ram(), addr + i * 2);
                 }
                 println!();
 
                 // row below
-                print!("0x{:04X}: ", u16::from(addr) + 16);
+                print!("0x{:04X}: ", u32::from(addr) + 16);
                 for i in 0..8 {
-                    if addr >= u12::try_from(0xFF0 as u16).unwrap() {
+                    if usize::from(addr) + 16 >= size {
                         print!("____ ");
                     } else {
-                        let ram = cpu.ram();
-                        let line = ram.read_word(addr + u12::from(16) + u12::from(i * 2));
-                        print!("{:04X} ", line)
+                        print_word(machine.cpu().ram(), addr + 16 + i * 2);
                     }
                 }
                 println!();
@@ -183,24 +207,32 @@ fn main() {
                     println!("{:?}", new_cpu);
                     continue;
                 }
-                cpu = new_cpu.unwrap();
+                machine = Machine::new(new_cpu.unwrap());
             }
 
             Command::Run => {
 
                 println!("Running... press Ctrl-C to pause");
                 loop {
-                    if breakpoints.contains(&cpu.program_counter()) {
-                        println!("Breakpoint hit at: {:#X}", cpu.program_counter());
+                    if breakpoints.contains(&machine.cpu().program_counter()) {
+                        println!("Breakpoint hit at: {:#X}", machine.cpu().program_counter());
                         break;
                     }
 
-                    if let Err(err) = cpu.step() {
-                        cpu.view();
+                    if tracing {
+                        println!("{}", describe_instruction(machine.cpu(), syntax));
+                    }
+                    if let Err(err) = machine.step(NO_KEYS) {
+                        machine.cpu().view();
                         println!("Error: {}", err);
                         break;
                     }
 
+                    if machine.cpu().halted() {
+                        println!("Program exited at: {:#X}", machine.cpu().program_counter());
+                        break;
+                    }
+
                     if terminate.lock().unwrap().clone() >= 1 {
                         println!("Pausing execution...");
                         break;
@@ -219,33 +251,32 @@ fn main() {
                 let opcode = opcode.unwrap();
                 let (a, b) = (opcode >> 8, opcode & 0x00FF);
                 let (a, b) = (a as u8, b as u8);
-                let decoded_opcode = cpu.decode((a, b));
-                cpu.execute(decoded_opcode, NO_KEYS);
+                if let Err(err) = machine.cpu_mut().execute_instr((a, b), NO_KEYS) {
+                    println!("Error: {}", err);
+                }
             }
 
             Command::Debug => {
                 println!("Debugging");
                 for i in 0..16 {
-                    println!("V{:X}: 0x{:04X}", i, cpu.vs[i]);
-                }
-                println!("PC: {:#X}", cpu.program_counter());
-                let pc = u12::try_from(cpu.program_counter()).unwrap();
-                let instr_hex = cpu.ram().read_word(pc);
-                let instr = ((instr_hex >> 8) as u8, instr_hex as u8);
-                match cpu.try_decode(instr) {
-                    Ok(opcode) => println!(
-                        "Instruction @ pc: 0x{:04X} | Decoded: {:?}",
-                        instr_hex, opcode
-                    ),
-                    Err(_) => println!(
-                        "Instruction @ pc: 0x{:04X} | Decoded: INVALID_OPCODE",
-                        instr_hex
-                    ),
+                    println!("V{:X}: 0x{:04X}", i, machine.cpu().vs[i]);
                 }
+                println!("PC: {:#X}", machine.cpu().program_counter());
+                println!("Instruction @ pc: {}", describe_instruction(machine.cpu(), syntax));
             }
 
+            Command::Trace => {
+                tracing = !tracing;
+                println!("Tracing {}", if tracing { "enabled" } else { "disabled" });
+            }
+
+            Command::Syntax => match rest.parse() {
+                Ok(new_syntax) => syntax = new_syntax,
+                Err(err) => println!("Error: {}", err),
+            },
+
             Command::View => {
-                cpu.view();
+                machine.cpu().view();
             }
 
             Command::Step => {
@@ -260,9 +291,12 @@ fn main() {
                     steps = result.unwrap();
                 }
                 for _ in 0..steps {
-                    let result = cpu.step();
+                    if tracing {
+                        println!("{}", describe_instruction(machine.cpu(), syntax));
+                    }
+                    let result = machine.step(NO_KEYS);
                     if result.is_err() {
-                        cpu.view();
+                        machine.cpu().view();
                         println!("Error: {}", result.unwrap_err());
                         break;
                     }
@@ -488,4 +522,19 @@ pub mod repl_tests {
         assert_eq!(command, Command::Memory);
         assert_eq!(rest, " test");
     }
+
+    #[test]
+    pub fn test_parse_trace_and_syntax_commands() {
+        let (command, rest) = parse_command("trace").unwrap();
+        assert_eq!(command, Command::Trace);
+        assert_eq!(rest, "");
+
+        let (command, rest) = parse_command("t").unwrap();
+        assert_eq!(command, Command::Trace);
+        assert_eq!(rest, "");
+
+        let (command, rest) = parse_command("syntax octo").unwrap();
+        assert_eq!(command, Command::Syntax);
+        assert_eq!(rest, " octo");
+    }
 }
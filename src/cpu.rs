@@ -1,26 +1,55 @@
 use crate::error::*;
+use crate::font::BIG_FONT_START;
+pub use crate::framebuffer::{Framebuffer, PLANES};
 use crate::memory::Memory;
+use crate::quirks::Quirks;
 use ux::*;
-use rand::{thread_rng, Rng};
+use crate::random::{RandomSource, XorShiftRng};
 
 pub const HEIGHT: usize = 32;
 pub const WIDTH: usize = 64;
 
-const HEIGHT_U8: u8 = 32;
-const WIDTH_U8: u8 = 64;
+// SUPER-CHIP's high-resolution mode
+pub const HIRES_HEIGHT: usize = 64;
+pub const HIRES_WIDTH: usize = 128;
 
 #[derive(Debug, Clone)]
 pub struct CPU {
-    ram: Memory,                   // 4kB of RAM
-    pub vram: [[bool; HEIGHT]; WIDTH], //vram containing pixel values, stored in column-major order
-    stack: Vec<u16>,               // stack, comprising of 2-byte values
-    pc: u12,                       // program counter
-    index: u12,                    // index register "I", used to point to addresses in memory
+    pub(crate) ram: Memory,                   // 4kB of RAM, or 64kB for XO-CHIP
+    pub vram: Framebuffer,                    // display memory, sized to the current resolution. Bit n of a pixel is set in plane n
+    pub(crate) hires: bool,                   // whether the 128x64 high-resolution mode is active
+    pub(crate) planes: u8,                    // XO-CHIP bitplanes selected for drawing, one bit per plane
+    pub(crate) stack: Vec<u16>,               // stack, comprising of 2-byte values
+    pub(crate) stack_limit: usize,            // maximum depth of the stack before CALL faults
+    pub(crate) pc: u16,                       // program counter
+    pub(crate) index: u16,                    // index register "I", used to point to addresses in memory
     pub delay : u8,                    // delay timer, decremented at a rate of 60Hz until it reaches 0
-    pub beep : u8,                     // sound timer, should emit a beeping sound as long as it's not 0
+    pub beep : u8,                     // sound timer, a tone sounds as long as it's not 0
     pub vs: [u8; 16], // general-purpose registers, labeled V0-VF
+    pub(crate) quirks: Quirks,                // interpreter behaviour to emulate
+    pub(crate) vblank: bool,                  // set once per 60Hz frame, consumed by DXYN when waiting for the display
+    pub(crate) rpl: [u8; 16],                 // SUPER-CHIP "RPL user flags", persisted across FX75/FX85
+    pub(crate) halted: bool,                  // set by 00FD, after which the CPU no longer executes instructions
+    pub(crate) audio_pattern: [u8; 16],       // XO-CHIP 1-bit audio pattern, played while the sound timer is non-zero
+    pub(crate) pitch: u8,                     // XO-CHIP playback rate of the audio pattern
+    pub(crate) rng: Box<dyn RandomSource>,    // source of random numbers for CXNN
+    instr_pc: u16,                 // address of the instruction being executed, reported on faults
+    instr: u16,                    // the instruction being executed, reported on faults
 }
 
+// The stack depth of the original interpreters
+pub const DEFAULT_STACK_LIMIT: usize = 16;
+
+// Result of executing a single instruction. Faults are given their location by ``CPU::execute``
+type OpResult = std::result::Result<(), Fault>;
+
+// Pitch register value at which the audio pattern plays at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+// Size of the address space of the original interpreters and of XO-CHIP
+const MEMORY_SIZE: usize = 0x1000;
+const EXTENDED_MEMORY_SIZE: usize = 0x10000;
+
 pub type KeyState = [bool; 16];
 pub const NO_KEYS : KeyState = [false; 16];
 struct NibblePair(u4, u4);
@@ -63,7 +92,7 @@ pub fn lower_nib(byte : u8) -> u4 {
     (byte & (0x0F)).try_into().unwrap()
 } 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     ClearScreen,
     Jump(u12),
@@ -105,42 +134,246 @@ pub enum Opcode {
     Decimal(u4),
     AddToIndex(u4),
     Random(u4, u8),
-    Font(u4)
+    Font(u4),
+    // SUPER-CHIP
+    ScrollDown(u4),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    BigFont(u4),
+    SaveFlags(u4),
+    LoadFlags(u4),
+    // XO-CHIP
+    SaveRange(u4, u4),
+    LoadRange(u4, u4),
+    LongSetI,
+    Plane(u4),
+    AudioPattern,
+    Pitch(u4),
+}
+
+impl Opcode {
+    /* Encodes the opcode into the instruction that decodes to it. LongSetI only encodes
+    F000; the address it loads is the word that follows it in memory */
+    pub fn encode(&self) -> (u8, u8) {
+        let [byte_1, byte_2] = self.to_word().to_be_bytes();
+        (byte_1, byte_2)
+    }
+
+    /* Encodes the opcode as a single big-endian word, as it is stored in memory */
+    pub fn to_word(&self) -> u16 {
+        let x = |nib: u4| u16::from(u8::from(nib)) << 8;
+        let y = |nib: u4| u16::from(u8::from(nib)) << 4;
+        let n = |nib: u4| u16::from(u8::from(nib));
+        let nnn = |addr: u12| u16::from(addr);
+        let nn = u16::from;
+
+        match *self {
+            Opcode::ClearScreen => 0x00E0,
+            Opcode::Jump(addr) => 0x1000 | nnn(addr),
+            Opcode::JumpOffset(addr) => 0xB000 | nnn(addr),
+            Opcode::SetReg(vx, value) => 0x6000 | x(vx) | nn(value),
+            Opcode::AddReg(vx, value) => 0x7000 | x(vx) | nn(value),
+            Opcode::SetI(addr) => 0xA000 | nnn(addr),
+            Opcode::Display(vx, vy, height) => 0xD000 | x(vx) | y(vy) | n(height),
+            Opcode::SkipEqImm(vx, value) => 0x3000 | x(vx) | nn(value),
+            Opcode::SkipNeqImm(vx, value) => 0x4000 | x(vx) | nn(value),
+            Opcode::SkipEqReg(vx, vy) => 0x5000 | x(vx) | y(vy),
+            Opcode::SkipNeqReg(vx, vy) => 0x9000 | x(vx) | y(vy),
+            Opcode::CallSubroutine(addr) => 0x2000 | nnn(addr),
+            Opcode::Return => 0x00EE,
+            Opcode::Set(vx, vy) => 0x8000 | x(vx) | y(vy),
+            Opcode::Or(vx, vy) => 0x8001 | x(vx) | y(vy),
+            Opcode::And(vx, vy) => 0x8002 | x(vx) | y(vy),
+            Opcode::Xor(vx, vy) => 0x8003 | x(vx) | y(vy),
+            Opcode::Add(vx, vy) => 0x8004 | x(vx) | y(vy),
+            Opcode::Subtract1(vx, vy) => 0x8005 | x(vx) | y(vy),
+            Opcode::ShiftR(vx, vy) => 0x8006 | x(vx) | y(vy),
+            Opcode::Subtract2(vx, vy) => 0x8007 | x(vx) | y(vy),
+            Opcode::ShiftL(vx, vy) => 0x800E | x(vx) | y(vy),
+            Opcode::Store(vx) => 0xF055 | x(vx),
+            Opcode::Load(vx) => 0xF065 | x(vx),
+            Opcode::SetRegToDelay(vx) => 0xF007 | x(vx),
+            Opcode::SetDelayToReg(vx) => 0xF015 | x(vx),
+            Opcode::SetSoundToReg(vx) => 0xF018 | x(vx),
+            Opcode::SkipIfKey(vx) => 0xE09E | x(vx),
+            Opcode::SkipIfNotKey(vx) => 0xE0A1 | x(vx),
+            Opcode::GetKey(vx) => 0xF00A | x(vx),
+            Opcode::Decimal(vx) => 0xF033 | x(vx),
+            Opcode::AddToIndex(vx) => 0xF01E | x(vx),
+            Opcode::Random(vx, mask) => 0xC000 | x(vx) | nn(mask),
+            Opcode::Font(vx) => 0xF029 | x(vx),
+            Opcode::ScrollDown(rows) => 0x00C0 | n(rows),
+            Opcode::ScrollRight => 0x00FB,
+            Opcode::ScrollLeft => 0x00FC,
+            Opcode::Exit => 0x00FD,
+            Opcode::LowRes => 0x00FE,
+            Opcode::HighRes => 0x00FF,
+            Opcode::BigFont(vx) => 0xF030 | x(vx),
+            Opcode::SaveFlags(vx) => 0xF075 | x(vx),
+            Opcode::LoadFlags(vx) => 0xF085 | x(vx),
+            Opcode::SaveRange(vx, vy) => 0x5002 | x(vx) | y(vy),
+            Opcode::LoadRange(vx, vy) => 0x5003 | x(vx) | y(vy),
+            Opcode::LongSetI => 0xF000,
+            Opcode::Plane(mask) => 0xF001 | x(mask),
+            Opcode::AudioPattern => 0xF002,
+            Opcode::Pitch(vx) => 0xF03A | x(vx),
+        }
+    }
 }
 
 impl CPU {
     /* Initializes the CPU by allocating a fresh block of
     memory and setting registers to their initial values*/
     pub fn new() -> Self {
-        let ram: Memory = Memory::new();
+        CPU::with_quirks(Quirks::default())
+    }
+
+    /* Initializes the CPU like ``new``, emulating the interpreter behaviour described by ``quirks`` */
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let ram: Memory = Memory::with_size(CPU::memory_size(&quirks));
 
         CPU {
             ram: ram,
-            vram: [[false; HEIGHT]; WIDTH],
+            vram: Framebuffer::new(WIDTH, HEIGHT),
+            hires: false,
+            planes: 0b01,
             stack: vec![],
-            pc: (0x200u16).try_into().unwrap(),
-            index: 0x0.into(),
+            stack_limit: DEFAULT_STACK_LIMIT,
+            pc: 0x200,
+            index: 0x0,
             delay: 0x00,
             beep: 0x00,
             vs: [0; 16],
+            quirks,
+            vblank: false,
+            rpl: [0; 16],
+            halted: false,
+            audio_pattern: [0; 16],
+            pitch: DEFAULT_PITCH,
+            rng: Box::new(XorShiftRng::from_entropy()),
+            instr_pc: 0x200,
+            instr: 0x0000,
         }
     }
 
+    fn memory_size(quirks: &Quirks) -> usize {
+        if quirks.extended_memory {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /* Changes the emulated interpreter behaviour, growing or shrinking memory if the address space changed */
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.ram.resize(CPU::memory_size(&quirks));
+        self.quirks = quirks;
+    }
+
+    /* Returns the width of the display in the current resolution */
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { WIDTH }
+    }
+
+    /* Returns the height of the display in the current resolution */
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { HEIGHT }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /* Returns the XO-CHIP bitplanes currently selected for drawing */
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn audio_pattern(&self) -> [u8; 16] {
+        self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /* Reseeds the random number generator so that CXNN produces a reproducible sequence */
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    /* Replaces the random number generator used by CXNN */
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+
+    /* Sets how many nested subroutine calls are allowed before CALL faults with a stack overflow */
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    /* Returns true once the program has exited through 00FD */
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
     /* Returns an immutable reference to the ram for debugging purposes */
     pub fn ram(&self) -> &Memory {
         &self.ram
     }
-    /* Loads a program into memory */
-    pub fn load_program(&mut self, data: &[u8]) -> () {
-        self.ram.load_program(data);
+    /* Loads a program into memory, returning an error if it does not fit */
+    pub fn load_program(&mut self, data: &[u8]) -> Result<()> {
+        self.ram.load_program(data)
     }
 
-    /* Simulates one CPU cycle, returning an error if decoding fails */
+    /* Simulates one CPU cycle with no keys pressed */
     pub fn step(&mut self) -> Result<()> {
-        let instr = self.fetch();
-        let opcode = self.try_decode(instr)?;
-        self.execute(opcode, NO_KEYS);
-        Ok(())
+        self.step_with_keys(NO_KEYS)
+    }
+
+    /* Simulates one CPU cycle, returning a RuntimeError if the instruction can't be fetched, decoded or executed */
+    pub fn step_with_keys(&mut self, keystate: KeyState) -> Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+        self.instr_pc = self.pc;
+        self.instr = 0x0000;
+        let instr = self.fetch()?;
+        self.run_instr(instr, keystate)
+    }
+
+    /* Decodes and executes ``instr`` as if it were located at the PC, without fetching it from memory */
+    pub fn execute_instr(&mut self, instr: (u8, u8), keystate: KeyState) -> Result<()> {
+        self.instr_pc = self.pc;
+        self.run_instr(instr, keystate)
+    }
+
+    fn run_instr(&mut self, instr: (u8, u8), keystate: KeyState) -> Result<()> {
+        self.instr = u16::from_be_bytes([instr.0, instr.1]);
+        let opcode = self
+            .try_decode(instr)
+            .map_err(|_| self.fault(Fault::UndecodableOpcode))?;
+        self.execute(opcode, keystate)
+    }
+
+    // Attaches the location of the current instruction to ``fault``
+    fn fault(&self, fault: Fault) -> Chip8Error {
+        Chip8Error::RuntimeError {
+            pc: self.instr_pc,
+            instr: self.instr,
+            fault,
+        }
     }
 
     pub fn decr_delay(&mut self) -> () {
@@ -155,28 +388,32 @@ impl CPU {
         }
     }
 
+    /* Ticks both timers. Should be called at 60Hz, which also marks the start of a new frame for DXYN */
     pub fn decr_timers(&mut self) -> () {
         self.decr_delay();
         self.decr_sound();
+        self.vblank = true;
     }
     /* Fetches the current instruction pointed to by the PC. Increments the PC by 2 */
-    pub fn fetch(&mut self) -> (u8, u8) {
-        let byte_1: u8 = self.ram.read(self.pc);
-        let byte_2: u8 = self.ram.read(self.pc + 1.into());
+    pub fn fetch(&mut self) -> Result<(u8, u8)> {
+        let byte_1: u8 = self.ram.read(self.pc).map_err(|fault| self.fault(fault))?;
+        let byte_2: u8 = self.ram.read(self.pc.wrapping_add(1)).map_err(|fault| self.fault(fault))?;
 
-        self.pc = self.pc + 2.into();
-        (byte_1, byte_2)
+        self.pc = self.pc.wrapping_add(2);
+        Ok((byte_1, byte_2))
     }
 
-    /* Decodes ``instr``, returning the Opcode it corresponds to */
-    pub fn decode(&self, instr: (u8, u8)) -> Opcode {
-        self.try_decode(instr)
-            .expect("Could not parse {instr:?} to opcode")
-    }
     /* Decodes ``instr``, returning the Opcode it corresponds to */
     pub fn try_decode(&self, instr: (u8, u8)) -> Result<Opcode> {
         let opcode = match instr {
             (0x00, 0xE0) => Opcode::ClearScreen,
+            // 00CN
+            (0x00, byte_2 @ 0xC0..=0xCF) => Opcode::ScrollDown(lower_nib(byte_2)),
+            (0x00, 0xFB) => Opcode::ScrollRight,
+            (0x00, 0xFC) => Opcode::ScrollLeft,
+            (0x00, 0xFD) => Opcode::Exit,
+            (0x00, 0xFE) => Opcode::LowRes,
+            (0x00, 0xFF) => Opcode::HighRes,
             (byte_1 @ 0x10..=0x1F, byte_2) => {
                 let nibs_1: NibblePair = byte_1.into();
                 let NibblePair(_, nib_1) = nibs_1;
@@ -220,6 +457,16 @@ impl CPU {
                 Opcode::SkipEqReg(nib_1, nib_2)
             }
 
+            // 5XY2
+            (byte_1 @ 0x50..=0x5F, byte_2) if lower_nib(byte_2) == u4::new(2) => {
+                Opcode::SaveRange(lower_nib(byte_1), upper_nib(byte_2))
+            }
+
+            // 5XY3
+            (byte_1 @ 0x50..=0x5F, byte_2) if lower_nib(byte_2) == u4::new(3) => {
+                Opcode::LoadRange(lower_nib(byte_1), upper_nib(byte_2))
+            }
+
             // 9XY0 
             (byte_1 @ 0x90..=0x9F, byte_2) if lower_nib(byte_2) == (0u8).try_into().unwrap() => {
                 let NibblePair(_, nib_1) = byte_1.into();
@@ -238,7 +485,7 @@ impl CPU {
             (0x00, 0xEE) => Opcode::Return,
 
             (byte_1 @ 0x80..=0x8F, byte_2) => {
-               self.decode_logarith((byte_1, byte_2)).unwrap()
+               self.decode_logarith((byte_1, byte_2))?
             }
 
             (byte_1 @ 0xF0..=0xFF, 0x55) => {
@@ -264,6 +511,32 @@ impl CPU {
             (byte_1 @ 0xF0..=0xFF, 0x29) => {
                 Opcode::Font(lower_nib(byte_1))
             }
+
+            // F000 NNNN
+            (0xF0, 0x00) => Opcode::LongSetI,
+
+            // F002
+            (0xF0, 0x02) => Opcode::AudioPattern,
+
+            (byte_1 @ 0xF0..=0xFF, 0x01) => {
+                Opcode::Plane(lower_nib(byte_1))
+            }
+
+            (byte_1 @ 0xF0..=0xFF, 0x3A) => {
+                Opcode::Pitch(lower_nib(byte_1))
+            }
+
+            (byte_1 @ 0xF0..=0xFF, 0x30) => {
+                Opcode::BigFont(lower_nib(byte_1))
+            }
+
+            (byte_1 @ 0xF0..=0xFF, 0x75) => {
+                Opcode::SaveFlags(lower_nib(byte_1))
+            }
+
+            (byte_1 @ 0xF0..=0xFF, 0x85) => {
+                Opcode::LoadFlags(lower_nib(byte_1))
+            }
             (byte_1 @ 0xF0..=0xFF,0x07) => {
                 Opcode::SetRegToDelay(lower_nib(byte_1))
             }
@@ -342,21 +615,25 @@ impl CPU {
 
     }
 
-    /* Executes the instruction indicated by ``opcode`` */
-    pub fn execute(&mut self, opcode: Opcode, keystate : KeyState) -> () {
+    /* Executes the instruction indicated by ``opcode``, returning a RuntimeError if it faults */
+    pub fn execute(&mut self, opcode: Opcode, keystate : KeyState) -> Result<()> {
+        self.dispatch(opcode, keystate).map_err(|fault| self.fault(fault))
+    }
+
+    fn dispatch(&mut self, opcode: Opcode, keystate : KeyState) -> OpResult {
         match opcode {
             Opcode::ClearScreen => self.op_00e0(),
             Opcode::Jump(addr) => self.op_1nnn(addr),
             Opcode::SetReg(reg, value) => self.op_6xnn(reg, value),
             Opcode::AddReg(reg, value) => self.op_7xnn(reg, value),
             Opcode::SetI(addr) => self.op_annn(addr),
-            Opcode::Display(x, y, n) => self.op_dxyn(x, y, n),
+            Opcode::Display(x, y, n) => self.op_dxyn(x, y, n)?,
             Opcode::SkipEqImm(x, nn) => self.op_3xnn(x, nn),
             Opcode::SkipNeqImm(x, nn) => self.op_4xnn(x, nn),
             Opcode::SkipEqReg(x, y) => self.op_5xy0(x, y),
             Opcode::SkipNeqReg(x, y) => self.op_9xy0(x, y),
-            Opcode::CallSubroutine(nnn) => self.op_2nnn(nnn),
-            Opcode::Return => self.op_00ee(),
+            Opcode::CallSubroutine(nnn) => self.op_2nnn(nnn)?,
+            Opcode::Return => self.op_00ee()?,
             Opcode::Set(x, y) => self.op_8xy0(x, y),
             Opcode::Or(x, y) => self.op_8xy1(x, y),
             Opcode::And(x, y) => self.op_8xy2(x, y),
@@ -364,32 +641,81 @@ impl CPU {
             Opcode::Add(x, y) => self.op_8xy4(x, y),
             Opcode::Subtract1(x, y) => self.op_8xy5(x, y),
             Opcode::Subtract2(x, y) => self.op_8xy7(x, y),
-            Opcode::ShiftR(x, y) => self.op_8xy6_modern(x, y),
-            Opcode::ShiftL(x, y) => self.op_8xye_modern(x, y),
-            Opcode::Store(x) => self.op_fx55_modern(x),
-            Opcode::Load(x) => self.op_fx65_modern(x),
-            Opcode::Decimal(x) => self.op_fx33(x),
+            Opcode::ShiftR(x, y) => self.op_8xy6(x, y),
+            Opcode::ShiftL(x, y) => self.op_8xye(x, y),
+            Opcode::Store(x) => self.op_fx55(x)?,
+            Opcode::Load(x) => self.op_fx65(x)?,
+            Opcode::Decimal(x) => self.op_fx33(x)?,
             Opcode::AddToIndex(x) => self.op_fx1e(x),
             Opcode::Random(x, nn) => self.op_cxnn(x, nn),
             Opcode::Font(x) => self.op_fx29(x),
             Opcode::SetRegToDelay(x) => self.op_fx07(x),
             Opcode::SetDelayToReg(x) => self.op_fx15(x),
             Opcode::SetSoundToReg(x) => self.op_fx18(x),
-            Opcode::JumpOffset(nnn) => self.op_bnnn_modern(nnn),
+            Opcode::JumpOffset(nnn) => self.op_bnnn(nnn),
             Opcode::SkipIfKey(x) => self.op_ex9e(x, keystate),
             Opcode::SkipIfNotKey(x) => self.op_exa1(x, keystate),
-            Opcode::GetKey(x) => self.op_fx0a(x, keystate)
-            
+            Opcode::GetKey(x) => self.op_fx0a(x, keystate),
+            Opcode::ScrollDown(n) => self.op_00cn(n),
+            Opcode::ScrollRight => self.op_00fb(),
+            Opcode::ScrollLeft => self.op_00fc(),
+            Opcode::Exit => self.op_00fd(),
+            Opcode::LowRes => self.op_00fe(),
+            Opcode::HighRes => self.op_00ff(),
+            Opcode::BigFont(x) => self.op_fx30(x),
+            Opcode::SaveFlags(x) => self.op_fx75(x),
+            Opcode::LoadFlags(x) => self.op_fx85(x),
+            Opcode::SaveRange(x, y) => self.op_5xy2(x, y)?,
+            Opcode::LoadRange(x, y) => self.op_5xy3(x, y)?,
+            Opcode::LongSetI => self.op_f000()?,
+            Opcode::Plane(n) => self.op_fn01(n),
+            Opcode::AudioPattern => self.op_f002()?,
+            Opcode::Pitch(x) => self.op_fx3a(x),
         }
+        Ok(())
     }
 
+    // Clears the selected planes
     fn op_00e0(&mut self) {
-        for i in 0..WIDTH {
-            self.vram[i] = [false; HEIGHT];
-        }
+        self.vram.clear(self.planes);
+    }
+
+    // Moves the selected planes of every pixel by (dx, dy), filling uncovered pixels with 0
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.vram.scroll(dx, dy, self.planes);
+    }
+
+    // Scroll the display down by ``n`` pixels
+    fn op_00cn(&mut self, n: u4) {
+        self.scroll(0, nib_to_usize(n) as isize);
+    }
+
+    // Scroll the display right by 4 pixels
+    fn op_00fb(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    // Scroll the display left by 4 pixels
+    fn op_00fc(&mut self) {
+        self.scroll(-4, 0);
+    }
+
+    fn op_00fd(&mut self) {
+        self.halted = true;
+    }
+
+    // Switching resolutions clears the display
+    fn op_00fe(&mut self) {
+        self.hires = false;
+        self.vram.resize(WIDTH, HEIGHT);
+    }
+
+    fn op_00ff(&mut self) {
+        self.hires = true;
+        self.vram.resize(HIRES_WIDTH, HIRES_HEIGHT);
     }
     fn op_1nnn(&mut self, nnn: u12) {
-        self.pc = nnn;
+        self.pc = nnn.into();
     }
 
     fn op_6xnn(&mut self, x: u4, nn: u8) {
@@ -403,58 +729,88 @@ impl CPU {
     }
 
     fn op_annn(&mut self, nnn: u12) {
-        self.index = nnn
+        self.index = nnn.into()
     }
 
-    fn op_dxyn(&mut self, x: u4, y: u4, n: u4) {
+    fn op_dxyn(&mut self, x: u4, y: u4, n: u4) -> OpResult {
+        // The VIP only drew sprites during the vertical blank, so at most one draw happens per frame
+        if self.quirks.display_wait {
+            if !self.vblank {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(());
+            }
+            self.vblank = false;
+        }
+
         let x_index: u8 = x.into();
         let x_index: usize = usize::from(x_index);
 
         let y_index: u8 = y.into();
         let y_index: usize = usize::from(y_index);
 
+        let width: u8 = self.width() as u8;
+        let height: u8 = self.height() as u8;
+
         let mut vx: u8 = self.vs[x_index];
         let mut vy: u8 = self.vs[y_index];
 
         //starting position of draw should be wrapped
-        vx = vx % WIDTH_U8;
-        vy = vy % HEIGHT_U8;
+        vx = vx % width;
+        vy = vy % height;
 
         self.vs[0xF] = 0;
 
-        let last_row: u8 = n.into();
-        for i in (0..last_row) {
-            let sprite_row: u8 = self.ram.read(self.index + i.into());
-            for col in (0..8) {
-                //Check whether we've hit the right edge of the screen
-                if vx + col >= WIDTH_U8 {
-                    break;
+        // DXY0 draws a 16x16 sprite stored as two bytes per row
+        let (last_row, sprite_width): (u8, u8) = match u8::from(n) {
+            0 => (16, 16),
+            n => (n, 8),
+        };
+        let bytes_per_row = sprite_width / 8;
+        let origin_y = vy;
+        let mut addr = self.index;
+        // With several planes selected, the sprite data for each plane follows the previous one
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) == 0 {
+                continue;
+            }
+            vy = origin_y;
+            for _ in 0..last_row {
+                let mut sprite_row: u16 = 0;
+                for _ in 0..bytes_per_row {
+                    sprite_row = (sprite_row << 8) | u16::from(self.ram.read(addr)?);
+                    addr = addr.wrapping_add(1);
                 }
-
-                //Grab the ``col``th pixel in sprite row
-                let sprite_pixel = (sprite_row >> (7 - col)) & (0x01);
-
-                let screen_x = usize::from(vx + col);
-                let screen_y = usize::from(vy);
-                let screen_pixel: bool = self.vram[screen_x][screen_y];
-                if sprite_pixel == 1 {
-                    if screen_pixel {
-                        self.vram[screen_x][screen_y] = false;
-                        self.vs[0xF] = 1;
-                    } else {
-                        self.vram[screen_x][screen_y] = true;
-                    }
+                //Check whether we've hit the bottom edge of the screen
+                if vy >= height && self.quirks.clip_sprites {
+                    continue;
+                }
+                let wrap = !self.quirks.clip_sprites;
+                let screen_y = usize::from(vy % height);
+                let collision = self.vram.xor_sprite_row(
+                    plane,
+                    usize::from(vx),
+                    screen_y,
+                    sprite_row,
+                    usize::from(sprite_width),
+                    wrap,
+                );
+                if collision {
+                    self.vs[0xF] = 1;
                 }
-            }
 
-            vy += 1;
+                vy += 1;
+            }
         }
+        Ok(())
     }
 
 
 
+    // Skips the next instruction, which is 4 bytes long if it is XO-CHIP's F000 NNNN
     fn skip_opcode(&mut self) -> () {
-        self.pc = self.pc + 2.into();
+        let long = self.quirks.extended_memory && self.ram.read_word(self.pc) == Ok(0xF000);
+        let length = if long { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(length);
     }
     fn op_3xnn(&mut self, x  : u4, nn : u8) -> () {
         let index : usize = nib_to_usize(x);
@@ -490,14 +846,22 @@ impl CPU {
         }
     }
 
-    fn op_2nnn(&mut self, nnn : u12) -> () {
-        self.stack.push(self.pc.into());
-        self.pc = nnn;
+    fn op_2nnn(&mut self, nnn : u12) -> OpResult {
+        if self.stack.len() >= self.stack_limit {
+            return Err(Fault::StackOverflow { limit: self.stack_limit });
+        }
+        self.stack.push(self.pc);
+        self.pc = nnn.into();
+        Ok(())
     }
 
-    fn op_00ee(&mut self) -> () {
-        let return_addr = self.stack.pop().unwrap();
-        self.pc = return_addr.try_into().unwrap();
+    fn op_00ee(&mut self) -> OpResult {
+        let return_addr = self.stack.pop().ok_or(Fault::StackUnderflow)?;
+        if usize::from(return_addr) >= self.ram.size() {
+            return Err(Fault::InvalidReturnAddress { addr: return_addr });
+        }
+        self.pc = return_addr;
+        Ok(())
     }
 
     fn load_from(&self, reg : u4) -> u8 {
@@ -517,19 +881,29 @@ impl CPU {
     fn op_8xy1(&mut self, x : u4, y : u4) -> () { 
         let vx = self.load_from(x);
         let vy = self.load_from(y);
-        self.save_to(x, vx | vy)
+        self.save_to(x, vx | vy);
+        self.reset_vf();
     }
     // Bitwise AND
     fn op_8xy2(&mut self, x : u4, y : u4) -> () { 
         let vx = self.load_from(x);
         let vy = self.load_from(y);
-        self.save_to(x, vx & vy)
+        self.save_to(x, vx & vy);
+        self.reset_vf();
     }
     // Bitwise XOR
     fn op_8xy3(&mut self, x : u4, y : u4) -> () { 
         let vx = self.load_from(x);
         let vy = self.load_from(y);
-        self.save_to(x, vx ^ vy)
+        self.save_to(x, vx ^ vy);
+        self.reset_vf();
+    }
+
+    // The VIP's logic routines clobbered VF as a side effect
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.vs[0xF] = 0;
+        }
     }
     // Add
     fn op_8xy4(&mut self, x : u4, y : u4) -> () { 
@@ -592,70 +966,135 @@ impl CPU {
     }
     // Returns first bit of a byte 
     fn first_bit(&self, byte : u8) -> u8 {
-        byte & (0x08) >> 3
+        (byte & 0x80) >> 7
     }
 
-    // Right shift (logical) 
-    fn op_8xy6_modern(&mut self, x : u4, _y : u4) -> () {
-        let vx = self.load_from(x);
-        self.vs[0xF] = self.last_bit(vx);
-
-        self.save_to(x, vx >> 1);
+    // The value to shift: VY on the VIP, VX in place on later interpreters
+    fn shift_source(&self, x : u4, y : u4) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.load_from(y)
+        } else {
+            self.load_from(x)
+        }
+    }
 
+    // Right shift (logical) 
+    fn op_8xy6(&mut self, x : u4, y : u4) {
+        let value = self.shift_source(x, y);
+        self.save_to(x, value >> 1);
+        self.vs[0xF] = self.last_bit(value);
     }
 
     // Left shift (logical) 
-    fn op_8xye_modern(&mut self, x : u4, _y : u4) -> () {
-        let vx = self.load_from(x);
-        self.vs[0xF] = self.first_bit(vx);
-
-        self.save_to(x, vx << 1);
+    fn op_8xye(&mut self, x : u4, y : u4) {
+        let value = self.shift_source(x, y);
+        self.save_to(x, value << 1);
+        self.vs[0xF] = self.first_bit(value);
     }
 
     // Store
-    fn op_fx55_modern(&mut self, x : u4) -> () {
-        let index : u12 = self.index;
+    fn op_fx55(&mut self, x : u4) -> OpResult {
+        let index : u16 = self.index;
         let last_reg : u8 = x.into();
-        for i in (0..=last_reg) {
+        for i in 0..=last_reg {
             let val = self.load_from(i.try_into().unwrap());
-            self.ram.write(index + i.into(), val)
+            self.ram.write(index.wrapping_add(i.into()), val)?;
         }
+        if self.quirks.load_store_increments_i {
+            self.index = index.wrapping_add(u16::from(last_reg) + 1);
+        }
+        Ok(())
     }
 
     // Load
-    fn op_fx65_modern(&mut self, x : u4) -> () {
-        let index : u12 = self.index;
+    fn op_fx65(&mut self, x : u4) -> OpResult {
+        let index : u16 = self.index;
         let last_reg : u8 = x.into();
-        for i in (0..=last_reg) {
-            let val = self.ram.read(index + i.into());
+        for i in 0..=last_reg {
+            let val = self.ram.read(index.wrapping_add(i.into()))?;
             self.save_to(i.try_into().unwrap(), val);
         }
+        if self.quirks.load_store_increments_i {
+            self.index = index.wrapping_add(u16::from(last_reg) + 1);
+        }
+        Ok(())
+    }
+
+    // Register range store: saves VX..VY (in either direction) at I without modifying I
+    fn op_5xy2(&mut self, x : u4, y : u4) -> OpResult {
+        let index = self.index;
+        for (offset, reg) in CPU::register_range(x, y).enumerate() {
+            self.ram.write(index.wrapping_add(offset as u16), self.vs[reg])?;
+        }
+        Ok(())
+    }
+
+    // Register range load: loads VX..VY (in either direction) from I without modifying I
+    fn op_5xy3(&mut self, x : u4, y : u4) -> OpResult {
+        let index = self.index;
+        for (offset, reg) in CPU::register_range(x, y).enumerate() {
+            self.vs[reg] = self.ram.read(index.wrapping_add(offset as u16))?;
+        }
+        Ok(())
+    }
+
+    fn register_range(x : u4, y : u4) -> Box<dyn Iterator<Item = usize>> {
+        let (x, y) = (nib_to_usize(x), nib_to_usize(y));
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    // Long index load: I = NNNN, read from the word following the instruction
+    fn op_f000(&mut self) -> OpResult {
+        self.index = self.ram.read_word(self.pc)?;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    // Selects the bitplanes affected by drawing, clearing and scrolling
+    fn op_fn01(&mut self, n : u4) {
+        self.planes = u8::from(n) & 0b11;
+    }
+
+    // Loads 16 bytes of audio pattern from I
+    fn op_f002(&mut self) -> OpResult {
+        for offset in 0..self.audio_pattern.len() {
+            self.audio_pattern[offset] = self.ram.read(self.index.wrapping_add(offset as u16))?;
+        }
+        Ok(())
+    }
+
+    fn op_fx3a(&mut self, x : u4) {
+        self.pitch = self.load_from(x);
     }
 
 
     // Decimal conversion
-    fn op_fx33(&mut self, x : u4) -> () {
+    fn op_fx33(&mut self, x : u4) -> OpResult {
         let vx = self.load_from(x);
         let first_digit = vx/100;
         let second_digit = (vx % 100)/10;
         let third_digit = vx % 10;
 
         let index = self.index;
-        self.ram.write(index, first_digit);
-        self.ram.write(index + 1.into(), second_digit);
-        self.ram.write(index + 2.into(), third_digit);
+        self.ram.write(index, first_digit)?;
+        self.ram.write(index.wrapping_add(1), second_digit)?;
+        self.ram.write(index.wrapping_add(2), third_digit)?;
+        Ok(())
     }
 
     fn op_fx1e(&mut self, x : u4) -> () {
         let vx = self.load_from(x);
-        self.index = self.index + vx.into();
+        self.index = self.index.wrapping_add(vx.into());
         //Note: Some interpreters would set the carry flag if the index register overflow from 0xFFF to 0x1000+ (outside of addressable range),
         // consider adding an option to do
     }
 
     fn op_cxnn(&mut self, x : u4, nn : u8) -> () {
-        let mut rng = thread_rng();
-        let rand : u8 = rng.gen();
+        let rand : u8 = self.rng.next_u8();
         let result = rand & nn;
         self.save_to(x, result)
     }
@@ -666,6 +1105,24 @@ impl CPU {
         self.index = sprite_addr.into();
     }
 
+    fn op_fx30(&mut self, x : u4) {
+        let vx = self.load_from(x);
+        let sprite_addr = BIG_FONT_START + u16::from(vx & 0x0F) * 10;
+        self.index = sprite_addr;
+    }
+
+    // Save V0..VX to the RPL user flags
+    fn op_fx75(&mut self, x : u4) {
+        let last_reg = nib_to_usize(x);
+        self.rpl[..=last_reg].copy_from_slice(&self.vs[..=last_reg]);
+    }
+
+    // Load V0..VX from the RPL user flags
+    fn op_fx85(&mut self, x : u4) {
+        let last_reg = nib_to_usize(x);
+        self.vs[..=last_reg].copy_from_slice(&self.rpl[..=last_reg]);
+    }
+
     fn op_fx07(&mut self, x : u4) -> () {
         self.save_to(x, self.delay)
     }
@@ -678,28 +1135,34 @@ impl CPU {
         self.beep = self.load_from(x)
     }
 
-    fn op_bnnn_modern(&mut self, nnn : u12) -> () {
+    // Jump with offset: NNN + V0 on the VIP, XNN + VX on the CHIP-48 and SUPER-CHIP
+    fn op_bnnn(&mut self, nnn : u12) {
         let nnn_16 : u16 = nnn.into(); 
-        let x : u4 = ((nnn_16 & (0xF00)) >> 8).try_into().unwrap();
+        let x : u4 = if self.quirks.jump_uses_vx {
+            ((nnn_16 & (0xF00)) >> 8).try_into().unwrap()
+        } else {
+            u4::new(0)
+        };
 
         let vx = self.load_from(x);
-        let addr = nnn + vx.into();
+        let addr = nnn_16 + u16::from(vx);
         self.pc = addr
     }
 
+    // Only the lower nibble of VX names a key
     fn op_ex9e(&mut self, x : u4, keystate : KeyState) -> () {
         let index = nib_to_usize(x);
-        let vx : usize = self.vs[index].into();
+        let vx : usize = (self.vs[index] & 0x0F).into();
         if keystate[vx] {
-            self.pc = self.pc + 2.into();
+            self.skip_opcode();
         }
     }
 
     fn op_exa1(&mut self, x : u4, keystate : KeyState) -> () {
         let index = nib_to_usize(x);
-        let vx : usize = self.vs[index].into();
+        let vx : usize = (self.vs[index] & 0x0F).into();
         if !keystate[vx] {
-            self.pc = self.pc + 2.into();
+            self.skip_opcode();
         }
     }
     fn op_fx0a(&mut self, x : u4, keystate : KeyState) -> () {
@@ -719,21 +1182,21 @@ impl CPU {
             return;
         }
 
-        self.pc = self.pc - 2.into()
+        self.pc = self.pc.wrapping_sub(2)
 
     }
     pub fn view(&self) -> () {
         print!("   ");
-        for _ in 0..WIDTH {
+        for _ in 0..self.width() {
             print!("-");
         }
         println!();
-        for y in 0..HEIGHT {
+        for y in 0..self.height() {
             print!("{:02}", y);
             print!("|");
-            for x in 0..WIDTH {
-                let pixel = self.vram[x][y];
-                if pixel {
+            for x in 0..self.width() {
+                let pixel = self.vram.get(x, y);
+                if pixel != 0 {
                     print!("■");
                 } else {
                     print!(" ");
@@ -743,13 +1206,391 @@ impl CPU {
         }
 
         print!("   ");
-        for _ in 0..WIDTH {
+        for _ in 0..self.width() {
             print!("-");
         }
         println!();
     }
 
     pub fn program_counter(&self) -> u16 {
-        u12::into(self.pc)
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+}
+
+#[cfg(test)]
+pub mod cpu_tests {
+    use super::*;
+    use crate::font::FONT_TABLE;
+    use crate::quirks::Platform;
+
+    fn run(cpu: &mut CPU, program: &[u8]) {
+        cpu.load_program(program).unwrap();
+        cpu.pc = 0x200;
+        for _ in 0..program.len() / 2 {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    pub fn test_shift_quirk() {
+        // V1 = 0x81, V0 = V1 >> 1
+        let program = [0x61, 0x81, 0x80, 0x16];
+
+        let mut vip = CPU::with_quirks(Platform::Chip8.quirks());
+        run(&mut vip, &program);
+        assert_eq!(vip.vs[0], 0x40);
+        assert_eq!(vip.vs[0xF], 1);
+
+        let mut schip = CPU::with_quirks(Platform::SuperChip.quirks());
+        run(&mut schip, &program);
+        assert_eq!(schip.vs[0], 0x00);
+        assert_eq!(schip.vs[0xF], 0);
+    }
+
+    #[test]
+    pub fn test_shift_left_sets_vf_to_msb() {
+        // V0 = 0x80, V0 <<= 1
+        let mut cpu = CPU::new();
+        run(&mut cpu, &[0x60, 0x80, 0x80, 0x0E]);
+        assert_eq!(cpu.vs[0], 0x00);
+        assert_eq!(cpu.vs[0xF], 1);
+
+        // V0 = 0x09, V0 <<= 1: low bits must not leak into VF
+        let mut cpu = CPU::new();
+        run(&mut cpu, &[0x60, 0x09, 0x80, 0x0E]);
+        assert_eq!(cpu.vs[0], 0x12);
+        assert_eq!(cpu.vs[0xF], 0);
+    }
+
+    #[test]
+    pub fn test_load_store_quirk() {
+        // I = 0x300, store V0..V2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+
+        let mut vip = CPU::with_quirks(Platform::Chip8.quirks());
+        run(&mut vip, &program);
+        assert_eq!(vip.index, 0x303);
+
+        let mut schip = CPU::with_quirks(Platform::SuperChip.quirks());
+        run(&mut schip, &program);
+        assert_eq!(schip.index, 0x300);
+    }
+
+    #[test]
+    pub fn test_jump_quirk() {
+        // V0 = 1, V2 = 2, jump to 0x210 + offset
+        let program = [0x60, 0x01, 0x62, 0x02, 0xB2, 0x10];
+
+        let mut vip = CPU::with_quirks(Platform::Chip8.quirks());
+        run(&mut vip, &program);
+        assert_eq!(vip.program_counter(), 0x211);
+
+        let mut schip = CPU::with_quirks(Platform::SuperChip.quirks());
+        run(&mut schip, &program);
+        assert_eq!(schip.program_counter(), 0x212);
+    }
+
+    #[test]
+    pub fn test_vf_reset_quirk() {
+        // VF = 1, V0 |= V1
+        let program = [0x6F, 0x01, 0x80, 0x11];
+
+        let mut vip = CPU::with_quirks(Platform::Chip8.quirks());
+        run(&mut vip, &program);
+        assert_eq!(vip.vs[0xF], 0);
+
+        let mut schip = CPU::with_quirks(Platform::SuperChip.quirks());
+        run(&mut schip, &program);
+        assert_eq!(schip.vs[0xF], 1);
+    }
+
+    #[test]
+    pub fn test_clip_quirk() {
+        // Draw the "0" glyph at (62, 30)
+        let program = [0x60, 62, 0x61, 30, 0xD0, 0x15];
+
+        let mut clipped = CPU::with_quirks(Platform::SuperChip.quirks());
+        run(&mut clipped, &program);
+        assert_ne!(clipped.vram.get(62, 31), 0);
+        assert_eq!(clipped.vram.get(1, 0), 0);
+
+        let mut wrapped = CPU::with_quirks(Platform::XoChip.quirks());
+        run(&mut wrapped, &program);
+        assert_ne!(wrapped.vram.get(62, 31), 0);
+        assert_ne!(wrapped.vram.get(1, 0), 0);
+    }
+
+    #[test]
+    pub fn test_display_wait_quirk() {
+        let mut cpu = CPU::with_quirks(Platform::Chip8.quirks());
+        cpu.load_program(&[0xD0, 0x15]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter(), 0x200);
+        cpu.decr_timers();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert_ne!(cpu.vram.get(0, 0), 0);
+    }
+
+    #[test]
+    pub fn test_hires_mode() {
+        let mut cpu = CPU::new();
+        run(&mut cpu, &[0x00, 0xFF]);
+        assert!(cpu.is_hires());
+        assert_eq!((cpu.width(), cpu.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+
+        // Draw the "0" glyph at (120, 60)
+        run(&mut cpu, &[0x60, 120, 0x61, 60, 0xD0, 0x15]);
+        assert_ne!(cpu.vram.get(120, 60), 0);
+        assert_eq!(cpu.vram.get(120, 0), 0);
+
+        run(&mut cpu, &[0x00, 0xFE]);
+        assert!(!cpu.is_hires());
+        assert_eq!(cpu.vram, Framebuffer::new(WIDTH, HEIGHT));
+    }
+
+    #[test]
+    pub fn test_large_sprite() {
+        // I = 0x300, a 16x16 sprite made of 0xFF bytes
+        let mut cpu = CPU::new();
+        let mut program = vec![0xA3, 0x00, 0xD0, 0x00];
+        program.resize(0x100, 0);
+        program.extend([0xFF; 32]);
+        cpu.load_program(&program).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_ne!(cpu.vram.get(15, 15), 0);
+        assert_eq!(cpu.vram.get(16, 15), 0);
+        assert_eq!(cpu.vram.get(15, 16), 0);
+    }
+
+    #[test]
+    pub fn test_scroll() {
+        let mut cpu = CPU::new();
+        cpu.vram.set(10, 10, 1);
+
+        run(&mut cpu, &[0x00, 0xC3]);
+        assert_ne!(cpu.vram.get(10, 13), 0);
+        assert_eq!(cpu.vram.get(10, 10), 0);
+
+        run(&mut cpu, &[0x00, 0xFB]);
+        assert_ne!(cpu.vram.get(14, 13), 0);
+
+        run(&mut cpu, &[0x00, 0xFC, 0x00, 0xFC]);
+        assert_ne!(cpu.vram.get(6, 13), 0);
+        assert_eq!(cpu.vram.get(14, 13), 0);
+    }
+
+    #[test]
+    pub fn test_exit() {
+        let mut cpu = CPU::new();
+        run(&mut cpu, &[0x00, 0xFD]);
+        assert!(cpu.halted());
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter(), 0x202);
+    }
+
+    #[test]
+    pub fn test_rpl_flags() {
+        // V0 = 1, V1 = 2, save V0..V1, clear them, load V0..V1
+        let mut cpu = CPU::new();
+        run(&mut cpu, &[0x60, 0x01, 0x61, 0x02, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85]);
+        assert_eq!(cpu.vs[0], 1);
+        assert_eq!(cpu.vs[1], 2);
+    }
+
+    #[test]
+    pub fn test_big_font() {
+        let mut cpu = CPU::new();
+        run(&mut cpu, &[0x60, 0x02, 0xF0, 0x30]);
+        assert_eq!(cpu.index, BIG_FONT_START + 20);
+    }
+
+    #[test]
+    pub fn test_extended_memory() {
+        let cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        assert_eq!(cpu.ram().size(), 0x10000);
+
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.ram().size(), 0x1000);
+        cpu.set_quirks(Platform::XoChip.quirks());
+        assert_eq!(cpu.ram().size(), 0x10000);
+    }
+
+    #[test]
+    pub fn test_long_index_load() {
+        let mut cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        cpu.load_program(&[0xF0, 0x00, 0xE1, 0x23]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.index, 0xE123);
+        assert_eq!(cpu.program_counter(), 0x204);
+    }
+
+    #[test]
+    pub fn test_skip_over_long_index_load() {
+        // Skip if V0 == 0 over F000 NNNN
+        let mut cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        cpu.load_program(&[0x30, 0x00, 0xF0, 0x00, 0xE1, 0x23]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter(), 0x206);
+
+        // Without the XO-CHIP extensions F000 is a single data word
+        let mut schip = CPU::with_quirks(Platform::SuperChip.quirks());
+        schip.load_program(&[0x30, 0x00, 0xF0, 0x00, 0xE1, 0x23]).unwrap();
+        schip.step().unwrap();
+        assert_eq!(schip.program_counter(), 0x204);
+    }
+
+    #[test]
+    pub fn test_register_range() {
+        // V1 = 1, V2 = 2, V3 = 3, I = 0x300, save V1..V3, load V3..V1 in reverse
+        let mut cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        run(&mut cpu, &[0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0x53, 0x13]);
+        assert_eq!(cpu.ram().read(0x300), Ok(1));
+        assert_eq!(cpu.ram().read(0x302), Ok(3));
+        assert_eq!(cpu.vs[1..=3], [3, 2, 1]);
+        assert_eq!(cpu.index, 0x300);
+    }
+
+    #[test]
+    pub fn test_bitplanes() {
+        // Select both planes, draw the "0" glyph with the "1" glyph as the second plane
+        let mut cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        run(&mut cpu, &[0xF3, 0x01, 0xD0, 0x05]);
+        assert_eq!(cpu.planes(), 0b11);
+        // "0" row 0 is 0xF0, "1" row 0 is 0x20
+        assert_eq!(cpu.vram.get(0, 0), 0b01);
+        assert_eq!(cpu.vram.get(2, 0), 0b11);
+
+        // Clearing plane 1 only leaves plane 2
+        run(&mut cpu, &[0xF1, 0x01, 0x00, 0xE0]);
+        assert_eq!(cpu.vram.get(0, 0), 0);
+        assert_eq!(cpu.vram.get(2, 0), 0b10);
+    }
+
+    #[test]
+    pub fn test_audio_registers() {
+        // V0 = 0x80, pitch = V0, I = 0 (the font), load pattern
+        let mut cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        run(&mut cpu, &[0x60, 0x80, 0xF0, 0x3A, 0xA0, 0x00, 0xF0, 0x02]);
+        assert_eq!(cpu.pitch(), 0x80);
+        assert_eq!(cpu.audio_pattern()[0..5], FONT_TABLE[0..5]);
+    }
+
+    fn fault_of(result: Result<()>) -> Fault {
+        match result {
+            Err(Chip8Error::RuntimeError { fault, .. }) => fault,
+            other => panic!("expected a runtime fault, got {other:?}"),
+        }
+    }
+
+    #[test]
+    pub fn test_stack_underflow() {
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0x00, 0xEE]).unwrap();
+        match cpu.step() {
+            Err(Chip8Error::RuntimeError { pc, instr, fault }) => {
+                assert_eq!(pc, 0x200);
+                assert_eq!(instr, 0x00EE);
+                assert_eq!(fault, Fault::StackUnderflow);
+            }
+            other => panic!("expected a runtime fault, got {other:?}"),
+        }
+    }
+
+    #[test]
+    pub fn test_stack_overflow() {
+        // Calls itself forever
+        let mut cpu = CPU::new();
+        cpu.set_stack_limit(4);
+        cpu.load_program(&[0x22, 0x00]).unwrap();
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(fault_of(cpu.step()), Fault::StackOverflow { limit: 4 });
+    }
+
+    #[test]
+    pub fn test_memory_out_of_range() {
+        // I = 0xFFF, store V0..V1
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0xAF, 0xFF, 0xF1, 0x55]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            fault_of(cpu.step()),
+            Fault::MemoryOutOfRange { addr: 0x1000, size: 0x1000 }
+        );
+    }
+
+    #[test]
+    pub fn test_fetch_out_of_range() {
+        // Jump to the last byte of memory
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0x1F, 0xFF]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            fault_of(cpu.step()),
+            Fault::MemoryOutOfRange { addr: 0x1000, size: 0x1000 }
+        );
+    }
+
+    #[test]
+    pub fn test_undecodable_opcode() {
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0xFF, 0xFF]).unwrap();
+        assert_eq!(fault_of(cpu.step()), Fault::UndecodableOpcode);
+    }
+
+    #[test]
+    pub fn test_sprite_at_bottom_edge() {
+        // Draw the "0" glyph at (0, 31) in every quirk mode
+        for platform in Platform::ALL {
+            let mut cpu = CPU::with_quirks(platform.quirks());
+            cpu.load_program(&[0x60, 0x00, 0x61, 31, 0xD0, 0x15]).unwrap();
+            for _ in 0..3 {
+                cpu.decr_timers();
+                cpu.step().unwrap();
+            }
+            assert_ne!(cpu.vram.get(0, 31), 0);
+        }
+    }
+
+    #[test]
+    pub fn test_program_too_large() {
+        let mut cpu = CPU::new();
+        assert!(cpu.load_program(&[0; 0xE00]).is_ok());
+        assert!(cpu.load_program(&[0; 0xE01]).is_err());
+    }
+
+    #[test]
+    pub fn test_seeded_random() {
+        // V0 = rand & 0xFF, V1 = rand & 0xFF
+        let program = [0xC0, 0xFF, 0xC1, 0xFF];
+        let mut a = CPU::new();
+        let mut b = CPU::new();
+        a.set_seed(42);
+        b.set_seed(42);
+        run(&mut a, &program);
+        run(&mut b, &program);
+        assert_eq!(a.vs[0..2], b.vs[0..2]);
+    }
+
+    #[test]
+    pub fn test_encode_round_trip() {
+        let cpu = CPU::new();
+        let mut decodable = 0;
+        for word in 0..=u16::MAX {
+            let [byte_1, byte_2] = word.to_be_bytes();
+            if let Ok(opcode) = cpu.try_decode((byte_1, byte_2)) {
+                assert_eq!(opcode.to_word(), word, "{:?} encoded wrongly", opcode);
+                assert_eq!(opcode.encode(), (byte_1, byte_2));
+                decodable += 1;
+            }
+        }
+        assert!(decodable > 30_000);
     }
 }
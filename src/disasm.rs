@@ -0,0 +1,319 @@
+//! A control-flow following disassembler.
+//!
+//! Starting from the entry point, every reachable instruction is decoded by following jumps,
+//! calls and both outcomes of skips. Bytes that are never reached, or that do not decode, are
+//! emitted as ``db`` data, so sprites interleaved with code don't derail the listing.
+
+use crate::cpu::{Opcode, CPU};
+use crate::syntax::Syntax;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Programs are loaded at this address
+pub const PROGRAM_START: u16 = 0x200;
+
+/// Settings for a disassembly listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Options {
+    /// Emit data one byte per line, with a comment drawing the byte as a sprite row
+    pub sprite_art: bool,
+    /// The mnemonic syntax of the listing
+    pub syntax: Syntax,
+}
+
+/// What a label marks, which decides its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+/// The result of following a ROM's control flow
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    rom: Vec<u8>,
+    instructions: BTreeMap<u16, (Opcode, u16)>, // decoded instructions and their length in bytes, by address
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl Disassembly {
+    /* Follows the control flow of ``rom``, loaded at 0x200, from its first instruction */
+    pub fn new(rom: &[u8]) -> Self {
+        let mut disassembly = Disassembly {
+            rom: rom.to_vec(),
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.trace(PROGRAM_START);
+        disassembly
+    }
+
+    fn end(&self) -> u32 {
+        u32::from(PROGRAM_START) + self.rom.len() as u32
+    }
+
+    fn word(&self, addr: u16) -> Option<u16> {
+        let offset = usize::from(addr.checked_sub(PROGRAM_START)?);
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Returns the address loaded by an F000 NNNN at ``addr``
+    fn long_operand(&self, addr: u16) -> Option<u16> {
+        self.word(addr.checked_add(2)?)
+    }
+
+    fn label(&mut self, addr: u16, kind: LabelKind) {
+        if (PROGRAM_START as u32..self.end()).contains(&u32::from(addr)) {
+            let label = self.labels.entry(addr).or_insert(kind);
+            *label = (*label).max(kind);
+        }
+    }
+
+    fn trace(&mut self, entry: u16) {
+        let cpu = CPU::new();
+        let mut pending = vec![entry];
+        while let Some(addr) = pending.pop() {
+            if self.instructions.contains_key(&addr) {
+                continue;
+            }
+            let Some(word) = self.word(addr) else { continue };
+            let Ok(opcode) = cpu.try_decode((word.to_be_bytes()[0], word.to_be_bytes()[1])) else {
+                continue;
+            };
+            let length = if opcode == Opcode::LongSetI { 4 } else { 2 };
+            if opcode == Opcode::LongSetI && self.long_operand(addr).is_none() {
+                continue;
+            }
+            self.instructions.insert(addr, (opcode, length));
+
+            // Execution can't continue past the end of the address space
+            let next = addr.checked_add(length);
+            match opcode {
+                Opcode::Jump(target) => {
+                    self.label(u16::from(target), LabelKind::Jump);
+                    pending.push(u16::from(target));
+                }
+                // The offset in V0 is unknown, so only the start of the jump table can be followed
+                Opcode::JumpOffset(target) => {
+                    self.label(u16::from(target), LabelKind::Jump);
+                    pending.push(u16::from(target));
+                }
+                Opcode::CallSubroutine(target) => {
+                    self.label(u16::from(target), LabelKind::Subroutine);
+                    pending.push(u16::from(target));
+                    pending.extend(next);
+                }
+                Opcode::Return | Opcode::Exit => {}
+                Opcode::SkipEqImm(..)
+                | Opcode::SkipNeqImm(..)
+                | Opcode::SkipEqReg(..)
+                | Opcode::SkipNeqReg(..)
+                | Opcode::SkipIfKey(..)
+                | Opcode::SkipIfNotKey(..) => {
+                    let Some(next) = next else { continue };
+                    let skipped = if self.word(next) == Some(0xF000) { 4 } else { 2 };
+                    pending.push(next);
+                    pending.extend(next.checked_add(skipped));
+                }
+                Opcode::SetI(target) => {
+                    self.label(u16::from(target), LabelKind::Data);
+                    pending.extend(next);
+                }
+                Opcode::LongSetI => {
+                    let target = self.long_operand(addr).unwrap();
+                    self.label(target, LabelKind::Data);
+                    pending.extend(next);
+                }
+                _ => pending.extend(next),
+            }
+        }
+    }
+
+    /* Returns the label name for ``addr``, if anything refers to it */
+    pub fn label_at(&self, addr: u16) -> Option<String> {
+        self.labels.get(&addr).map(|kind| match kind {
+            LabelKind::Subroutine => format!("sub_{:03X}", addr),
+            LabelKind::Jump => format!("loc_{:03X}", addr),
+            LabelKind::Data => format!("dat_{:03X}", addr),
+        })
+    }
+
+    /* Returns the number of decoded instructions */
+    pub fn instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+
+    fn target(&self, addr: u16) -> String {
+        self.label_at(addr).unwrap_or_else(|| format!("0x{:03X}", addr))
+    }
+
+    /* Renders the disassembly as assembler source */
+    pub fn listing(&self, options: Options) -> String {
+        let mut out = String::new();
+        let mut addr = u32::from(PROGRAM_START);
+        let mut data: Vec<u8> = Vec::new();
+        let mut data_start = addr;
+        let mut placed = Vec::new(); // labels written at the start of a line
+
+        // Bytes past the end of the 64 KiB address space can't be reached and are listed as data
+        while addr < self.end() {
+            let pc = u16::try_from(addr).ok();
+            let instruction = pc.and_then(|pc| self.instructions.get(&pc));
+            let labelled = pc.is_some_and(|pc| self.labels.contains_key(&pc));
+            if !data.is_empty() && (instruction.is_some() || labelled) {
+                self.write_data(&mut out, data_start, &data, options);
+                data.clear();
+            }
+            if let Some(label) = pc.and_then(|pc| self.label_at(pc)) {
+                match options.syntax {
+                    Syntax::Cowgod => writeln!(out, "{}:", label).unwrap(),
+                    Syntax::Octo => writeln!(out, ": {}", label).unwrap(),
+                }
+                placed.extend(pc);
+            }
+
+            match (pc, instruction) {
+                (Some(pc), Some(&(opcode, length))) => {
+                    let mut text = opcode.format_with(options.syntax, |target| self.target(target));
+                    let mut bytes = format!("{:04X}", self.word(pc).unwrap());
+                    if opcode == Opcode::LongSetI {
+                        let target = self.long_operand(pc).unwrap();
+                        text = format!("{} {}", text, self.target(target));
+                        bytes = format!("{} {:04X}", bytes, target);
+                    }
+                    let comment = format!("{:03X}: {}", pc, bytes);
+                    writeln!(out, "{}", line(options.syntax, &text, &comment)).unwrap();
+                    addr += u32::from(length);
+                }
+                _ => {
+                    if data.is_empty() {
+                        data_start = addr;
+                    }
+                    data.push(self.rom[(addr - u32::from(PROGRAM_START)) as usize]);
+                    addr += 1;
+                }
+            }
+        }
+        if !data.is_empty() {
+            self.write_data(&mut out, data_start, &data, options);
+        }
+
+        // Targets in the middle of an instruction can't be labelled, so they become constants
+        let mut constants = String::new();
+        for &addr in self.labels.keys().filter(|addr| !placed.contains(addr)) {
+            let name = self.label_at(addr).unwrap();
+            match options.syntax {
+                Syntax::Cowgod => writeln!(constants, "{} = 0x{:03X}", name, addr).unwrap(),
+                Syntax::Octo => writeln!(constants, ":const {} 0x{:03X}", name, addr).unwrap(),
+            }
+        }
+        constants + &out
+    }
+
+    fn write_data(&self, out: &mut String, start: u32, data: &[u8], options: Options) {
+        let per_line = if options.sprite_art { 1 } else { 8 };
+        for (i, bytes) in data.chunks(per_line).enumerate() {
+            let values: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            let text = match options.syntax {
+                Syntax::Cowgod => format!("db {}", values.join(", ")),
+                Syntax::Octo => values.join(" "),
+            };
+            let comment = if options.sprite_art {
+                (0..8)
+                    .map(|bit| if bytes[0] & (0x80 >> bit) != 0 { '#' } else { '.' })
+                    .collect()
+            } else {
+                format!("{:03X}", start as usize + i * per_line)
+            };
+            writeln!(out, "{}", line(options.syntax, &text, &comment)).unwrap();
+        }
+    }
+}
+
+fn line(syntax: Syntax, text: &str, comment: &str) -> String {
+    let marker = match syntax {
+        Syntax::Cowgod => ';',
+        Syntax::Octo => '#',
+    };
+    format!("    {:<24} {} {}", text, marker, comment)
+}
+
+#[cfg(test)]
+pub mod disasm_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_follows_control_flow() {
+        let rom = [
+            0x22, 0x08, // 200: CALL sub_208
+            0xA2, 0x0C, // 202: LD I, dat_20C
+            0x12, 0x0A, // 204: JP loc_20A
+            0xFF, 0xFF, // 206: unreachable
+            0x00, 0xEE, // 208: RET
+            0x00, 0xFD, // 20A: EXIT
+            0x3C, 0x42, // 20C: sprite
+        ];
+        let disassembly = Disassembly::new(&rom);
+        assert_eq!(disassembly.instruction_count(), 5);
+
+        let listing = disassembly.listing(Options::default());
+        let lines: Vec<&str> = listing.lines().map(|line| line.split(';').next().unwrap().trim()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "CALL sub_208",
+                "LD I, dat_20C",
+                "JP loc_20A",
+                "db 0xFF, 0xFF",
+                "sub_208:",
+                "RET",
+                "loc_20A:",
+                "EXIT",
+                "dat_20C:",
+                "db 0x3C, 0x42",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_skips_and_sprite_art() {
+        // The skip must follow both outcomes, but the F000 it may skip over is 4 bytes long
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD, 0x81];
+        let disassembly = Disassembly::new(&rom);
+        assert_eq!(disassembly.instruction_count(), 3);
+
+        let listing = disassembly.listing(Options {
+            sprite_art: true,
+            ..Options::default()
+        });
+        assert!(listing.contains("LD I, LONG 0x1234"));
+        assert!(listing.contains("; #......#"));
+    }
+
+    #[test]
+    pub fn test_end_of_address_space() {
+        // V0 = 0 up to a skip at 0xFFFC over an F000 whose address would lie past 0xFFFF
+        let mut rom = [0x60, 0x00].repeat((0xFFFC - 0x200) / 2);
+        rom.extend([0x30, 0x00, 0xF0, 0x00]);
+        let disassembly = Disassembly::new(&rom);
+        assert_eq!(disassembly.instruction_count(), (0xFFFC - 0x200) / 2 + 1);
+
+        // A ROM running past the address space is listed to its last byte
+        rom.extend([0xAB, 0xCD]);
+        let listing = Disassembly::new(&rom).listing(Options::default());
+        assert!(listing.trim_end().ends_with("db 0xF0, 0x00, 0xAB, 0xCD ; FFFE"));
+    }
+
+    #[test]
+    pub fn test_octo_listing() {
+        let rom = [0x12, 0x02, 0x60, 0x01, 0x00, 0xFD];
+        let listing = Disassembly::new(&rom).listing(Options {
+            syntax: Syntax::Octo,
+            ..Options::default()
+        });
+        let lines: Vec<&str> = listing.lines().map(|line| line.split('#').next().unwrap().trim()).collect();
+        assert_eq!(lines, vec!["jump loc_202", ": loc_202", "v0 := 0x01", "exit"]);
+    }
+}
@@ -0,0 +1,169 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use crate::cpu::{CPU, HIRES_HEIGHT, HIRES_WIDTH};
+use crate::error::*;
+use crate::frontend::DisplaySink;
+use crate::palette::{Palette, PaletteCycle, Rgb};
+use crate::phosphor::Phosphor;
+use crate::upscale::{Overlay, Scaling, MAX_FACTOR};
+
+// the scaling factor determining how much we should "blow up" each pixel by
+pub const SCALE : u32 = 20;
+
+// Bytes per pixel of the RGB24 texture
+const BYTES_PER_PIXEL: usize = 3;
+// Opacity of the scanline and grid overlays
+const OVERLAY_ALPHA: u8 = 96;
+
+/* Returns the colours of the display's pixels, row by row. With ``phosphor``, pixels are
+coloured by how brightly they still glow instead */
+pub fn draw_screen(cpu : &CPU, palette : &Palette, phosphor : Option<&Phosphor>) -> Vec<Rgb> {
+    let mut pixels = Vec::with_capacity(cpu.width() * cpu.height());
+    draw_screen_into(cpu, palette, phosphor, &mut pixels);
+    pixels
+}
+
+/* Like ``draw_screen``, but writes the colours into ``pixels``, reusing its allocation */
+pub fn draw_screen_into(cpu : &CPU, palette : &Palette, phosphor : Option<&Phosphor>, pixels : &mut Vec<Rgb>) {
+    pixels.clear();
+    for (y, row) in cpu.vram.rows().enumerate() {
+        for x in 0..cpu.width() {
+            pixels.push(match phosphor {
+                Some(phosphor) => phosphor.color(x, y, palette),
+                None => palette.color(row.get(x)),
+            });
+        }
+    }
+}
+
+// Copies ``width`` pixels wide rows of ``pixels`` into an RGB24 buffer whose rows are ``pitch`` bytes apart
+fn upload(pixels: &[Rgb], width: usize, buffer: &mut [u8], pitch: usize) {
+    for (y, row) in pixels.chunks(width).enumerate() {
+        let line = &mut buffer[y * pitch..y * pitch + width * BYTES_PER_PIXEL];
+        for (bytes, color) in line.chunks_mut(BYTES_PER_PIXEL).zip(row) {
+            bytes.copy_from_slice(color);
+        }
+    }
+}
+
+/// Draws the display into an SDL window. Each frame is enlarged by the upscaling filter and
+/// uploaded into a streaming texture, which the renderer then scales to the window.
+pub struct SdlDisplay<'a> {
+    canvas: Canvas<Window>,
+    texture: Texture<'a>, // large enough for the filtered high-resolution display; smaller images use its top-left corner
+    palettes: PaletteCycle, // the palette in use and those the palette hotkey switches to
+    phosphor: Option<Phosphor>,
+    scaling: Scaling,
+    pixels: Vec<Rgb>, // the coloured display and its filtered version, kept to avoid allocating every frame
+    scaled: Vec<Rgb>,
+}
+
+impl<'a> SdlDisplay<'a> {
+    /* Creates the streaming texture with ``texture_creator``, which must outlive the display */
+    pub fn new(canvas: Canvas<Window>, texture_creator: &'a TextureCreator<WindowContext>) -> Result<Self> {
+        let texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                (HIRES_WIDTH * MAX_FACTOR) as u32,
+                (HIRES_HEIGHT * MAX_FACTOR) as u32,
+            )
+            .map_err(|err| Chip8Error::DisplayError(err.to_string()))?;
+        Ok(SdlDisplay {
+            canvas,
+            texture,
+            palettes: PaletteCycle::default(),
+            phosphor: None,
+            scaling: Scaling::default(),
+            pixels: Vec::new(),
+            scaled: Vec::new(),
+        })
+    }
+
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palettes = PaletteCycle::new(palette);
+        self
+    }
+
+    /* Fades pixels out over several frames, keeping ``decay`` of their brightness each frame */
+    pub fn with_phosphor(mut self, decay: f32) -> Self {
+        self.phosphor = Some(Phosphor::new(decay));
+        self
+    }
+
+    pub fn with_scaling(mut self, scaling: Scaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    // Darkens the lower half of every row, or outlines every pixel, of the display at ``view``
+    fn draw_overlay(&mut self, view: Rect, width: usize, height: usize) -> Result<()> {
+        let cell_width = view.width() as f32 / width as f32;
+        let cell_height = view.height() as f32 / height as f32;
+        let mut rects = Vec::new();
+        match self.scaling.overlay {
+            Overlay::None => return Ok(()),
+            // Lines on tiny pixels would hide the display rather than texture it
+            Overlay::Scanlines if cell_height >= 2.0 => {
+                for row in 0..height {
+                    let top = view.y() + (row as f32 * cell_height + cell_height / 2.0) as i32;
+                    rects.push(Rect::new(view.x(), top, view.width(), (cell_height / 2.0) as u32));
+                }
+            }
+            Overlay::Grid if cell_width >= 3.0 && cell_height >= 3.0 => {
+                for column in 0..width {
+                    let left = view.x() + (column as f32 * cell_width) as i32;
+                    rects.push(Rect::new(left, view.y(), 1, view.height()));
+                }
+                for row in 0..height {
+                    let top = view.y() + (row as f32 * cell_height) as i32;
+                    rects.push(Rect::new(view.x(), top, view.width(), 1));
+                }
+            }
+            _ => return Ok(()),
+        }
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, OVERLAY_ALPHA));
+        self.canvas.fill_rects(&rects).map_err(Chip8Error::DisplayError)
+    }
+}
+
+impl DisplaySink for SdlDisplay<'_> {
+    fn present(&mut self, cpu: &CPU) -> Result<()> {
+        if let Some(phosphor) = &mut self.phosphor {
+            phosphor.update(&cpu.vram);
+        }
+        let palette = self.palettes.current();
+        draw_screen_into(cpu, &palette, self.phosphor.as_ref(), &mut self.pixels);
+        let filter = self.scaling.filter;
+        let (width, height) = (cpu.width() * filter.factor(), cpu.height() * filter.factor());
+        filter.apply_into(&self.pixels, cpu.width(), cpu.height(), &mut self.scaled);
+
+        let area = Rect::new(0, 0, width as u32, height as u32);
+        let scaled = &self.scaled;
+        self.texture
+            .with_lock(area, |buffer, pitch| upload(scaled, width, buffer, pitch))
+            .map_err(Chip8Error::DisplayError)?;
+
+        // Letterboxing around the display takes the background colour
+        let [r, g, b] = palette.color(0);
+        self.canvas.set_blend_mode(BlendMode::None);
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
+        self.canvas.clear();
+        let (window_width, window_height) = self.canvas.output_size().map_err(Chip8Error::DisplayError)?;
+        let mode = self.scaling.mode;
+        let (x, y, view_width, view_height) =
+            mode.viewport(cpu.width() as u32, cpu.height() as u32, window_width, window_height);
+        let view = Rect::new(x, y, view_width, view_height);
+        self.canvas.copy(&self.texture, area, view).map_err(Chip8Error::DisplayError)?;
+        self.draw_overlay(view, cpu.width(), cpu.height())?;
+        // Blocks until the next vertical blank when the canvas was built with vsync
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn cycle_palette(&mut self) -> Option<String> {
+        Some(self.palettes.advance().name.to_string())
+    }
+}
@@ -13,4 +13,40 @@ pub enum Chip8Error {
     InstructionParseError(String),
     #[error("Could not convert number: {0}")]
     NumericalConversionError(String),
+    #[error("Unknown platform: {0}")]
+    UnknownPlatform(String),
+    #[error("Could not load save state: {0}")]
+    SaveStateError(String),
+    #[error("Could not parse key script: {0}")]
+    KeyScriptError(String),
+    #[error("Could not load keymap: {0}")]
+    KeymapError(String),
+    #[error("Could not write output: {0}")]
+    OutputError(String),
+    #[error("Display error: {0}")]
+    DisplayError(String),
+    #[error("Input error: {0}")]
+    InputError(String),
+    #[error("Audio error: {0}")]
+    AudioError(String),
+    #[error("{file}:{line}: {reason}")]
+    AssemblyError { file: String, line: usize, reason: String },
+    #[error("CPU fault at {pc:#05X} while executing {instr:#06X}: {fault}")]
+    RuntimeError { pc: u16, instr: u16, fault: Fault },
+}
+
+/// Reasons the CPU can stop executing a ROM. Reported inside ``Chip8Error::RuntimeError``
+/// along with the location and contents of the faulting instruction.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    #[error("stack overflow, the stack is limited to {limit} levels")]
+    StackOverflow { limit: usize },
+    #[error("stack underflow, returned from a subroutine with an empty stack")]
+    StackUnderflow,
+    #[error("memory access at {addr:#06X} is out of range of {size} bytes of memory")]
+    MemoryOutOfRange { addr: usize, size: usize },
+    #[error("return address {addr:#06X} is outside of memory")]
+    InvalidReturnAddress { addr: u16 },
+    #[error("opcode could not be decoded")]
+    UndecodableOpcode,
 }
@@ -0,0 +1,198 @@
+//! The display memory: a bit-packed framebuffer with one ``u128`` per row and bitplane.
+
+// Number of XO-CHIP bitplanes; plane n of a pixel is bit n of its value
+pub const PLANES: usize = 2;
+// Widest display that fits in a row
+pub const MAX_WIDTH: usize = 128;
+
+/// One row of the display, holding the pixels of every plane
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Row([u128; PLANES]);
+
+impl Row {
+    /* Returns the pixel at column ``x``, with bit n set if it is lit in plane n */
+    pub fn get(&self, x: usize) -> u8 {
+        let mut pixel = 0;
+        for (plane, bits) in self.0.iter().enumerate() {
+            pixel |= (((bits >> (127 - x)) & 1) as u8) << plane;
+        }
+        pixel
+    }
+
+    /* Returns the bits of ``plane``; column x is bit 127 - x, so the leftmost pixel is the highest bit */
+    pub fn plane(&self, plane: usize) -> u128 {
+        self.0[plane]
+    }
+}
+
+/// A display of up to 128 pixels across, stored as rows of bits so that a sprite row
+/// is drawn with a single shift and XOR per plane.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    rows: Vec<Row>,
+}
+
+impl Framebuffer {
+    /* Creates a blank framebuffer. Panics if ``width`` is over 128 pixels */
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && width <= MAX_WIDTH, "framebuffer width {width} is not supported");
+        Framebuffer {
+            width,
+            height,
+            rows: vec![Row([0; PLANES]); height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /* Changes the size of the framebuffer, clearing it */
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Framebuffer::new(width, height);
+    }
+
+    // Bits of a row that lie within the display
+    fn row_mask(&self) -> u128 {
+        !0 << (MAX_WIDTH - self.width)
+    }
+
+    /* Returns the pixel at (x, y), with bit n set if it is lit in plane n */
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        assert!(x < self.width, "x coordinate {x} is outside of the framebuffer");
+        self.rows[y].get(x)
+    }
+
+    /* Sets the pixel at (x, y) in every plane from the bits of ``pixel`` */
+    pub fn set(&mut self, x: usize, y: usize, pixel: u8) {
+        assert!(x < self.width, "x coordinate {x} is outside of the framebuffer");
+        let bit = 1u128 << (127 - x);
+        for (plane, bits) in self.rows[y].0.iter_mut().enumerate() {
+            if pixel & (1 << plane) != 0 {
+                *bits |= bit;
+            } else {
+                *bits &= !bit;
+            }
+        }
+    }
+
+    pub fn row(&self, y: usize) -> Row {
+        self.rows[y]
+    }
+
+    /* Iterates over the rows from top to bottom */
+    pub fn rows(&self) -> impl Iterator<Item = Row> + '_ {
+        self.rows.iter().copied()
+    }
+
+    /* XORs the ``sprite_width`` low bits of ``bits`` into ``plane`` at (x, y), most significant bit
+    leftmost. Pixels past the right edge wrap around if ``wrap`` is set and are dropped otherwise.
+    Returns whether any lit pixel was turned off */
+    pub fn xor_sprite_row(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        bits: u16,
+        sprite_width: usize,
+        wrap: bool,
+    ) -> bool {
+        let aligned = u128::from(bits) << (MAX_WIDTH - sprite_width);
+        let mut sprite = aligned >> x;
+        if wrap && x + sprite_width > self.width {
+            sprite |= aligned << (self.width - x);
+        }
+        sprite &= self.row_mask();
+
+        let row = &mut self.rows[y].0[plane];
+        let collision = *row & sprite != 0;
+        *row ^= sprite;
+        collision
+    }
+
+    /* Clears the planes selected by the bits of ``planes`` */
+    pub fn clear(&mut self, planes: u8) {
+        for row in self.rows.iter_mut() {
+            for (plane, bits) in row.0.iter_mut().enumerate() {
+                if planes & (1 << plane) != 0 {
+                    *bits = 0;
+                }
+            }
+        }
+    }
+
+    /* Moves the selected planes by (dx, dy), filling uncovered pixels with 0 */
+    pub fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+        let mask = self.row_mask();
+        let old = self.rows.clone();
+        let height = self.height as isize;
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            let src_y = y as isize - dy;
+            for (plane, bits) in row.0.iter_mut().enumerate() {
+                if planes & (1 << plane) == 0 {
+                    continue;
+                }
+                let src = if (0..height).contains(&src_y) {
+                    old[src_y as usize].0[plane]
+                } else {
+                    0
+                };
+                let shifted = if dx >= 0 {
+                    src.checked_shr(dx as u32).unwrap_or(0)
+                } else {
+                    src.checked_shl(dx.unsigned_abs() as u32).unwrap_or(0)
+                };
+                *bits = shifted & mask;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod framebuffer_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_and_set() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+        framebuffer.set(63, 31, 0b11);
+        framebuffer.set(0, 0, 0b10);
+        assert_eq!(framebuffer.get(63, 31), 0b11);
+        assert_eq!(framebuffer.get(0, 0), 0b10);
+        assert_eq!(framebuffer.get(1, 0), 0);
+        assert_eq!(framebuffer.row(0).plane(1), 1 << 127);
+        framebuffer.set(63, 31, 0);
+        assert_eq!(framebuffer.get(63, 31), 0);
+        assert_eq!(framebuffer.rows().count(), 32);
+    }
+
+    #[test]
+    pub fn test_xor_sprite_row() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+        assert!(!framebuffer.xor_sprite_row(0, 60, 0, 0xFF, 8, false));
+        assert_eq!(framebuffer.row(0).plane(0) >> 64, 0xF);
+        assert!(framebuffer.xor_sprite_row(0, 60, 0, 0x80, 8, false));
+        assert_eq!(framebuffer.get(60, 0), 0);
+
+        // Wrapping carries the rest of the sprite to the left edge
+        assert!(!framebuffer.xor_sprite_row(0, 62, 1, 0xFFFF, 16, true));
+        assert_eq!(framebuffer.row(1).plane(0) >> 64, 0xFFFC_0000_0000_0003);
+    }
+
+    #[test]
+    pub fn test_scroll() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+        framebuffer.set(10, 10, 0b11);
+        framebuffer.scroll(4, 2, 0b01);
+        assert_eq!(framebuffer.get(14, 12), 0b01);
+        assert_eq!(framebuffer.get(10, 10), 0b10);
+        framebuffer.scroll(-64, 0, 0b11);
+        assert_eq!(framebuffer, Framebuffer::new(64, 32));
+    }
+}
@@ -0,0 +1,411 @@
+//! Backend-agnostic frontend: the traits a display, audio output and input device implement,
+//! and the loop that drives a ``Machine`` with them one 60Hz frame at a time.
+
+use crate::cpu::{KeyState, CPU, NO_KEYS};
+use crate::error::*;
+use crate::machine::{FrameResult, Machine, FRAME_RATE};
+use crate::savestate;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Receives the display contents once per frame
+pub trait DisplaySink {
+    /* Shows the current contents of ``cpu``'s display */
+    fn present(&mut self, cpu: &CPU) -> Result<()>;
+
+    /* Switches to the next colour palette and returns its name. Displays without colours ignore
+    this and return None */
+    fn cycle_palette(&mut self) -> Option<String> {
+        None
+    }
+}
+
+/// Receives the state of the sound timer once per frame
+pub trait AudioSink {
+    /* Called at the end of every frame; the sink should sound for the frame if ``sounding`` is set,
+    which tells whether the sound timer was running before the frame ticked it. ``cpu`` gives the
+    XO-CHIP audio pattern */
+    fn frame(&mut self, cpu: &CPU, sounding: bool) -> Result<()>;
+
+    /* Silences the sink, or unsilences it if it was muted, returning whether it is now muted.
+    Sinks that can't be muted ignore this and return None */
+    fn toggle_mute(&mut self) -> Option<bool> {
+        None
+    }
+}
+
+/// Requests made by the user that are handled by the frontend rather than the emulated program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    SaveState,
+    LoadState,
+    /// Step back one frame instead of running the next one
+    Rewind,
+    ToggleMute,
+    CyclePalette,
+}
+
+/// Supplies the state of the hex keypad and frontend commands
+pub trait InputSource {
+    /* Processes pending input, returning the commands issued since the last call */
+    fn poll(&mut self) -> Vec<Command>;
+
+    /* Returns which of the 16 keys are currently held */
+    fn keystate(&self) -> KeyState;
+
+    /* Returns the statuses gathered since the last call, such as controllers being connected */
+    fn take_status(&mut self) -> Vec<Status> {
+        Vec::new()
+    }
+}
+
+/// A display that discards every frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullDisplay;
+
+impl DisplaySink for NullDisplay {
+    fn present(&mut self, _cpu: &CPU) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An audio output that stays silent
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn frame(&mut self, _cpu: &CPU, _sounding: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An optional audio output, which stays silent like ``NullAudio`` when absent
+impl<A: AudioSink> AudioSink for Option<A> {
+    fn frame(&mut self, cpu: &CPU, sounding: bool) -> Result<()> {
+        match self {
+            Some(sink) => sink.frame(cpu, sounding),
+            None => Ok(()),
+        }
+    }
+
+    fn toggle_mute(&mut self) -> Option<bool> {
+        self.as_mut()?.toggle_mute()
+    }
+}
+
+/// An input device with no keys held that never issues commands
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullInput;
+
+impl InputSource for NullInput {
+    fn poll(&mut self) -> Vec<Command> {
+        Vec::new()
+    }
+
+    fn keystate(&self) -> KeyState {
+        NO_KEYS
+    }
+}
+
+/// Something that happened while running that the user may want to know about.
+/// The frontend hands these to its status callback; it is up to the application to show them.
+#[derive(Debug)]
+pub enum Status {
+    StateSaved(String),
+    StateLoaded(String),
+    /// The sound was muted, if set, or unmuted
+    Muted(bool),
+    /// The display switched to the palette with the given name
+    Palette(String),
+    /// A game controller with the given name was plugged in
+    ControllerConnected(String),
+    /// An error that did not stop the emulator, such as a save state that could not be loaded
+    Error(Chip8Error),
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::StateSaved(path) => write!(f, "Saved state to {}", path),
+            Status::StateLoaded(path) => write!(f, "Loaded state from {}", path),
+            Status::Muted(true) => write!(f, "Sound muted"),
+            Status::Muted(false) => write!(f, "Sound unmuted"),
+            Status::Palette(name) => write!(f, "Palette: {}", name),
+            Status::ControllerConnected(name) => write!(f, "Connected {}", name),
+            Status::Error(err) => write!(f, "Error: {}", err),
+        }
+    }
+}
+
+/// Why the frontend stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// The user asked to quit
+    Quit,
+    /// The program exited through 00FD
+    Halted,
+}
+
+/// Ties a display, audio output and input source to a machine
+pub struct Frontend<D, A, I> {
+    pub display: D,
+    pub audio: A,
+    pub input: I,
+    state_path: Option<String>, // where SaveState and LoadState commands write and read
+    throttle: bool,             // whether frames are paced to 60Hz in real time
+    on_status: Box<dyn FnMut(Status)>,
+}
+
+impl<D: DisplaySink, A: AudioSink, I: InputSource> Frontend<D, A, I> {
+    pub fn new(display: D, audio: A, input: I) -> Self {
+        Frontend {
+            display,
+            audio,
+            input,
+            state_path: None,
+            throttle: true,
+            on_status: Box::new(|_| {}),
+        }
+    }
+
+    /* Calls ``on_status`` with every status the frontend reports. Without one they are discarded */
+    pub fn with_status(mut self, on_status: impl FnMut(Status) + 'static) -> Self {
+        self.on_status = Box::new(on_status);
+        self
+    }
+
+    /* Sets the file used for save states; without one the save and load commands are ignored */
+    pub fn with_state_path(mut self, path: impl Into<String>) -> Self {
+        self.state_path = Some(path.into());
+        self
+    }
+
+    /* Runs frames as fast as possible instead of pacing them to 60Hz */
+    pub fn unthrottled(mut self) -> Self {
+        self.throttle = false;
+        self
+    }
+
+    /* Runs ``machine`` until the user quits or the program halts. CPU faults are returned as errors */
+    pub fn run(&mut self, machine: &mut Machine) -> Result<Exit> {
+        let frame_duration = Duration::new(0, 1_000_000_000u32 / FRAME_RATE);
+        loop {
+            let frame_start = Instant::now();
+            if let Some(exit) = self.frame(machine)? {
+                return Ok(exit);
+            }
+
+            // Sleep for whatever is left of the frame
+            if self.throttle {
+                if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+                    std::thread::sleep(remaining);
+                }
+            }
+        }
+    }
+
+    /* Handles input, runs one frame and presents it. Returns the reason to stop, if any */
+    pub fn frame(&mut self, machine: &mut Machine) -> Result<Option<Exit>> {
+        let mut rewinding = false;
+        for command in self.input.poll() {
+            match command {
+                Command::Quit => return Ok(Some(Exit::Quit)),
+                Command::SaveState => self.save_state(machine),
+                Command::LoadState => self.load_state(machine),
+                Command::Rewind => rewinding = true,
+                Command::ToggleMute => {
+                    if let Some(muted) = self.audio.toggle_mute() {
+                        (self.on_status)(Status::Muted(muted));
+                    }
+                }
+                Command::CyclePalette => {
+                    if let Some(name) = self.display.cycle_palette() {
+                        (self.on_status)(Status::Palette(name));
+                    }
+                }
+            }
+        }
+        for status in self.input.take_status() {
+            (self.on_status)(status);
+        }
+
+        if rewinding {
+            if let Err(err) = machine.rewind() {
+                (self.on_status)(Status::Error(err));
+            }
+        } else {
+            match machine.run_frame(self.input.keystate()) {
+                FrameResult::Completed => {}
+                FrameResult::Halted => return Ok(Some(Exit::Halted)),
+                FrameResult::Faulted(err) => return Err(err),
+            }
+        }
+
+        self.display.present(machine.cpu())?;
+        self.audio.frame(machine.cpu(), machine.sounding())?;
+        Ok(None)
+    }
+
+    fn save_state(&mut self, machine: &Machine) {
+        let Some(path) = &self.state_path else { return };
+        let status = match savestate::save_to_file(machine.cpu(), path) {
+            Ok(()) => Status::StateSaved(path.clone()),
+            Err(err) => Status::Error(err),
+        };
+        (self.on_status)(status);
+    }
+
+    fn load_state(&mut self, machine: &mut Machine) {
+        let Some(path) = &self.state_path else { return };
+        let status = match savestate::restore_from_file(machine.cpu_mut(), path) {
+            Ok(()) => Status::StateLoaded(path.clone()),
+            Err(err) => Status::Error(err),
+        };
+        (self.on_status)(status);
+    }
+}
+
+#[cfg(test)]
+pub mod frontend_tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingDisplay {
+        frames: usize,
+    }
+
+    impl DisplaySink for CountingDisplay {
+        fn present(&mut self, _cpu: &CPU) -> Result<()> {
+            self.frames += 1;
+            Ok(())
+        }
+    }
+
+    // Issues the given commands one per frame, then quits
+    struct ScriptedInput {
+        commands: Vec<Command>,
+    }
+
+    impl InputSource for ScriptedInput {
+        fn poll(&mut self) -> Vec<Command> {
+            if self.commands.is_empty() {
+                vec![Command::Quit]
+            } else {
+                vec![self.commands.remove(0)]
+            }
+        }
+
+        fn keystate(&self) -> KeyState {
+            NO_KEYS
+        }
+    }
+
+    fn machine(program: &[u8]) -> Machine {
+        let mut cpu = CPU::new();
+        cpu.load_program(program).unwrap();
+        Machine::with_ips(cpu, 600)
+    }
+
+    #[test]
+    pub fn test_runs_until_halted() {
+        // V0 += 1 until it reaches 5, then exit during the second frame
+        let mut machine = machine(&[0x70, 0x01, 0x30, 0x05, 0x12, 0x00, 0x00, 0xFD]);
+        let mut frontend = Frontend::new(CountingDisplay::default(), NullAudio, NullInput).unthrottled();
+        assert_eq!(frontend.run(&mut machine).unwrap(), Exit::Halted);
+        assert_eq!(frontend.display.frames, 1);
+        assert_eq!(machine.cpu().vs[0], 5);
+    }
+
+    #[test]
+    pub fn test_commands() {
+        // Infinite loop of V0 += 1
+        let mut machine = machine(&[0x70, 0x01, 0x12, 0x00]);
+        machine.enable_rewind(10);
+        let input = ScriptedInput {
+            commands: vec![Command::Rewind, Command::Rewind],
+        };
+        let mut frontend = Frontend::new(CountingDisplay::default(), NullAudio, input).unthrottled();
+        machine.run_frame(NO_KEYS);
+        machine.run_frame(NO_KEYS);
+        assert_eq!(frontend.run(&mut machine).unwrap(), Exit::Quit);
+        assert_eq!(machine.cpu().vs[0], 0);
+        assert_eq!(frontend.display.frames, 2);
+    }
+
+    #[test]
+    pub fn test_status_is_reported() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut machine = machine(&[0x70, 0x01, 0x12, 0x00]);
+        let path = std::env::temp_dir().join(format!("chip8-crab-status-{}.state", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let input = ScriptedInput {
+            commands: vec![Command::SaveState, Command::LoadState],
+        };
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&reported);
+        let mut frontend = Frontend::new(NullDisplay, NullAudio, input)
+            .with_state_path(path.clone())
+            .with_status(move |status| sink.borrow_mut().push(status.to_string()))
+            .unthrottled();
+        frontend.run(&mut machine).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let reported = reported.borrow();
+        assert_eq!(*reported, [format!("Saved state to {path}"), format!("Loaded state from {path}")]);
+
+        // A missing state file is reported rather than stopping the frontend
+        let input = ScriptedInput {
+            commands: vec![Command::LoadState],
+        };
+        let errors = Rc::new(RefCell::new(0));
+        let sink = Rc::clone(&errors);
+        let mut frontend = Frontend::new(NullDisplay, NullAudio, input)
+            .with_state_path(path)
+            .with_status(move |status| {
+                if let Status::Error(_) = status {
+                    *sink.borrow_mut() += 1;
+                }
+            })
+            .unthrottled();
+        assert_eq!(frontend.run(&mut machine).unwrap(), Exit::Quit);
+        assert_eq!(*errors.borrow(), 1);
+    }
+
+    #[test]
+    pub fn test_sound_timer_sounds_for_its_value() {
+        #[derive(Default)]
+        struct CountingAudio {
+            sounding: usize,
+        }
+
+        impl AudioSink for CountingAudio {
+            fn frame(&mut self, _cpu: &CPU, sounding: bool) -> Result<()> {
+                self.sounding += usize::from(sounding);
+                Ok(())
+            }
+        }
+
+        for frames in [1, 3] {
+            // V0 = frames, sound timer = V0, loop forever
+            let mut machine = machine(&[0x60, frames, 0xF0, 0x18, 0x12, 0x04]);
+            let mut frontend = Frontend::new(NullDisplay, CountingAudio::default(), NullInput).unthrottled();
+            for _ in 0..10 {
+                frontend.frame(&mut machine).unwrap();
+            }
+            assert_eq!(frontend.audio.sounding, usize::from(frames));
+        }
+    }
+
+    #[test]
+    pub fn test_faults_are_returned() {
+        let mut machine = machine(&[0x00, 0xEE]);
+        let mut frontend = Frontend::new(NullDisplay, NullAudio, NullInput).unthrottled();
+        assert!(matches!(
+            frontend.run(&mut machine),
+            Err(Chip8Error::RuntimeError { .. })
+        ));
+    }
+}
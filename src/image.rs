@@ -0,0 +1,132 @@
+//! Encoders for dumping the display to image files without any external dependencies.
+
+use crate::cpu::{CPU, PLANES};
+use crate::error::*;
+use std::fs;
+use std::path::Path;
+
+// Grey levels of each combination of XO-CHIP planes, matching the SDL frontend's colours
+const SHADES: [u8; 4] = [0, 255, 170, 85];
+
+/* Encodes the display as a binary PBM, with lit pixels in black */
+pub fn to_pbm(cpu: &CPU) -> Vec<u8> {
+    let (width, height) = (cpu.width(), cpu.height());
+    let mut data = format!("P4\n{} {}\n", width, height).into_bytes();
+    // Rows are packed with the leftmost pixel in the highest bit, just like PBM
+    for row in cpu.vram.rows() {
+        let lit = (0..PLANES).fold(0, |lit, plane| lit | row.plane(plane));
+        data.extend(&lit.to_be_bytes()[..width / 8]);
+    }
+    data
+}
+
+/* Encodes the display as an 8-bit greyscale PNG */
+pub fn to_png(cpu: &CPU) -> Vec<u8> {
+    let (width, height) = (cpu.width(), cpu.height());
+
+    // Every scanline starts with filter type 0 (none)
+    let mut pixels = Vec::with_capacity((width + 1) * height);
+    for row in cpu.vram.rows() {
+        pixels.push(0);
+        pixels.extend((0..width).map(|x| SHADES[usize::from(row.get(x) & 0b11)]));
+    }
+
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bits per sample, greyscale, deflate, no filtering, no interlacing
+    header.extend([8, 0, 0, 0, 0]);
+
+    let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut data, b"IHDR", &header);
+    png_chunk(&mut data, b"IDAT", &zlib_stored(&pixels));
+    png_chunk(&mut data, b"IEND", &[]);
+    data
+}
+
+/* Writes the display to ``filename``, choosing PNG or PBM from its extension */
+pub fn save(cpu: &CPU, filename: &str) -> Result<()> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let data = match extension.as_deref() {
+        Some("png") => to_png(cpu),
+        Some("pbm") => to_pbm(cpu),
+        _ => {
+            return Err(Chip8Error::OutputError(format!(
+                "{filename} must end in .png or .pbm"
+            )))
+        }
+    };
+    fs::write(filename, data)
+        .map_err(|err| Chip8Error::OutputError(format!("could not write {filename}: {err}")))
+}
+
+fn png_chunk(out: &mut Vec<u8>, tag: &[u8; 4], payload: &[u8]) {
+    out.extend((payload.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(tag);
+    out.extend(payload);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// The display is at most a few kilobytes, so the data is stored uncompressed
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        out.push(u8::from(i == blocks.len() - 1));
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(*block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend(((b << 16) | a).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+pub mod image_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_pbm() {
+        let mut cpu = CPU::new();
+        cpu.vram.set(0, 0, 1);
+        cpu.vram.set(9, 1, 1);
+        let pbm = to_pbm(&cpu);
+        let header = b"P4\n64 32\n";
+        assert_eq!(&pbm[..header.len()], header);
+        assert_eq!(pbm.len(), header.len() + 8 * 32);
+        assert_eq!(pbm[header.len()], 0x80);
+        assert_eq!(pbm[header.len() + 9], 0x40);
+    }
+
+    #[test]
+    pub fn test_png_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        let png = to_png(&CPU::new());
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+    }
+}
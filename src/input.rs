@@ -1,187 +1,252 @@
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use std::time::Duration;
 use sdl2::keyboard::Scancode;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::rect::Rect;
-use std::sync::{Arc, Mutex};
-use crate::cpu::{CPU, HEIGHT, WIDTH, KeyState};
-use crate::loader::load_program;
-
-// the scaling factor determining how much we should "blow up" each pixel by
-const SCALE : u32 = 20;
-
-
-pub fn one_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::Num1)
-} 
-pub fn two_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::Num2)
-} 
-pub fn three_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::Num3)
-} 
-pub fn four_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::Num4)
+use crate::audio::ToneSettings;
+use crate::cpu::{HEIGHT, WIDTH, KeyState};
+use crate::display::{SdlDisplay, SCALE};
+use crate::error::*;
+use crate::keymap::{Keymap, PAD_PREFIX};
+use crate::frontend::{Command, Exit, Frontend, InputSource, Status};
+use crate::machine::Machine;
+use crate::palette::Palette;
+use crate::sound::SdlAudio;
+use crate::upscale::Scaling;
+
+/// A keyboard key or controller input that presses a keypad key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(Scancode),
+    Button(Button),
+    Axis(Axis, bool), // pressed while the axis is past the deadzone, in the positive direction if set
+}
+
+impl Binding {
+    /* Looks up a key name from a keymap: an SDL scancode name, or ``Pad:`` and an SDL
+    controller button name or axis name followed by + or - */
+    pub fn from_name(name: &str) -> Option<Binding> {
+        let Some(input) = name.strip_prefix(PAD_PREFIX) else {
+            return Scancode::from_name(name).map(Binding::Key);
+        };
+        if let Some(button) = Button::from_string(input) {
+            return Some(Binding::Button(button));
+        }
+        let (axis, positive) = match input.strip_suffix('+') {
+            Some(axis) => (axis, true),
+            None => (input.strip_suffix('-')?, false),
+        };
+        Axis::from_string(axis).map(|axis| Binding::Axis(axis, positive))
+    }
 }
 
-pub fn q_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::Q)
-}
-pub fn w_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::W)
-}
-pub fn e_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::E)
-}
-pub fn r_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::R)
+/// The bindings of each keypad key, resolved from a ``Keymap``
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputMap {
+    bindings: [Vec<Binding>; 16],
+    deadzone: i16,
 }
 
-pub fn a_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::A)
-}
-pub fn s_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::S)
-}
-pub fn d_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::D)
-}
-pub fn f_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::F)
-}
-pub fn z_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::Z)
-}
-pub fn x_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::X)
-}
-pub fn c_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::C)
-}
-pub fn v_pressed(e: &sdl2::EventPump) -> bool {
-    e.keyboard_state().is_scancode_pressed(Scancode::V)
-}
-
-pub fn binary_to_rgb(color : bool) -> Color {
-
-    match color {
-        true => Color::RGB(255, 255, 255),
-        false => Color::RGB(0, 0, 0)
+impl InputMap {
+    /* Looks up every key name in ``keymap`` */
+    pub fn new(keymap: &Keymap) -> Result<Self> {
+        let mut bindings: [Vec<Binding>; 16] = Default::default();
+        for (key, key_bindings) in bindings.iter_mut().enumerate() {
+            for name in keymap.names(key) {
+                let binding = Binding::from_name(name).ok_or_else(|| {
+                    Chip8Error::KeymapError(format!("unknown key {name} for key {key:X}"))
+                })?;
+                key_bindings.push(binding);
+            }
+        }
+        Ok(InputMap {
+            bindings,
+            deadzone: keymap.deadzone(),
+        })
     }
-
 }
 
-pub fn draw_screen(vram : &([[bool; HEIGHT]; WIDTH]), canvas : &mut Canvas<Window>) -> () {
-    
-    //TODO: add some offset from the boundaries of the canvas
-    let mut canvas_row : i32 = 0;
-    for row in (0..HEIGHT) {
-        let mut canvas_col = 0;
-        for col in (0..WIDTH) { 
-            let pixel = vram[col][row];
-            let pixel_color = binary_to_rgb(pixel);
-
-            // Draw a SCALING_FACTOR x SCALING_FACTOR rect at (canvas_col, canvas_row)
-            canvas.set_draw_color(pixel_color);
-            canvas.fill_rect(Rect::new(canvas_col, canvas_row, SCALE, SCALE));
-
-            // shift canvas_row and canvas_col to correspond to next pixel location
-            canvas_col += SCALE as i32;
+/* Returns which keypad keys are held, a key being held while any of its bindings is pressed on
+the keyboard or on any of ``controllers`` */
+pub fn get_keystate(e : &sdl2::EventPump, controllers : &[GameController], map : &InputMap) -> KeyState {
+    let keyboard = e.keyboard_state();
+    let pressed = |binding: &Binding| match *binding {
+        Binding::Key(code) => keyboard.is_scancode_pressed(code),
+        Binding::Button(button) => controllers.iter().any(|pad| pad.button(button)),
+        Binding::Axis(axis, positive) => controllers.iter().any(|pad| {
+            let value = i32::from(pad.axis(axis));
+            let deadzone = i32::from(map.deadzone);
+            if positive { value > deadzone } else { value < -deadzone }
+        }),
+    };
+
+    let mut keystate = KeyState::default();
+    for (held, bindings) in keystate.iter_mut().zip(&map.bindings) {
+        *held = bindings.iter().any(pressed);
+    }
+    keystate
+}
+
+/// Keyboard and game controller input through the SDL event pump. Controllers can be plugged in
+/// and removed while running
+pub struct SdlInput {
+    event_pump: sdl2::EventPump,
+    controller_subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    map: InputMap,
+    status: Vec<Status>, // controller connections and errors not yet taken by the frontend
+}
+
+impl SdlInput {
+    pub fn new(event_pump: sdl2::EventPump, controller_subsystem: GameControllerSubsystem, map: InputMap) -> Self {
+        SdlInput {
+            event_pump,
+            controller_subsystem,
+            controllers: Vec::new(),
+            map,
+            status: Vec::new(),
         }
-        canvas_row += SCALE as i32;
     }
-
-
 }
 
-pub fn get_keystate(e : &sdl2::EventPump) -> KeyState {
-    [
-        x_pressed(e), // 0
-        one_pressed(e), // 1
-        two_pressed(e),  // 2
-        three_pressed(e), // 3
-        q_pressed(e), // 4
-        w_pressed(e), // 5
-        e_pressed(e), // 6
-        a_pressed(e), // 7
-        s_pressed(e), // 8
-        d_pressed(e), // 9
-        z_pressed(e), // A
-        c_pressed(e), // B
-        four_pressed(e), // C
-        r_pressed(e), // D
-        f_pressed(e), // E
-        v_pressed(e) // F
-    ]
-}
-
-pub fn run() -> Arc<Mutex<Vec<Scancode>>>{
-    let codes = Arc::new(Mutex::new(Vec::new()));
-    let codes_ext = codes.clone();
-    std::thread::spawn(move || {
-        let mut input = String::new();
-        println!("Provide path to ROM:");
-        std::io::stdin().read_line(&mut input).unwrap();
-        let path = input.trim();
-        let mut cpu = load_program(path).unwrap();   
-
-        let sdl_context = sdl2::init().expect("sdl2 init failed");
-        let video_subsystem = sdl_context.video().expect("video subsystem failed");
-        let width :u32 = <usize as TryInto<u32>>::try_into(WIDTH).unwrap() * SCALE;
-        let height:u32 = <usize as TryInto<u32>>::try_into(HEIGHT).unwrap() * SCALE;
-
-        let window = video_subsystem
-            .window("Chip8-Crab", width, height)
-            .position_centered()
-            .opengl()
-            .build()
-            .map_err(|e| e.to_string()).expect("window build failed");
-
-        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string()).expect("canvas build failed");
-
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
-        let mut event_pump = sdl_context.event_pump().expect("event pump failed");
-        loop {
-            for event in event_pump.poll_iter() {
-                
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => {
-                        return;
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => commands.push(Command::Quit),
+                // F5 saves a snapshot next to the ROM, F9 restores it
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => commands.push(Command::SaveState),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => commands.push(Command::LoadState),
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleMute),
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::CyclePalette),
+                // SDL also reports the controllers that are connected at startup as added
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match self.controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            self.status.push(Status::ControllerConnected(controller.name()));
+                            self.controllers.push(controller);
+                        }
+                        Err(err) => self.status.push(Status::Error(Chip8Error::InputError(format!(
+                            "could not open controller {}: {}",
+                            which, err
+                        )))),
                     }
-                    _ => {}
                 }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|controller| controller.instance_id() != which);
+                }
+                _ => {}
             }
+        }
 
-            let keystate = get_keystate(&event_pump);
-            codes.lock().unwrap().clear();
-            for scancode in event_pump.keyboard_state().pressed_scancodes() {
-                codes.lock().unwrap().push(scancode);
-            }
+        // Holding backspace steps backwards one frame at a time instead of running
+        if self.event_pump.keyboard_state().is_scancode_pressed(Scancode::Backspace) {
+            commands.push(Command::Rewind);
+        }
+        commands
+    }
 
+    fn keystate(&self) -> KeyState {
+        get_keystate(&self.event_pump, &self.controllers, &self.map)
+    }
 
+    fn take_status(&mut self) -> Vec<Status> {
+        std::mem::take(&mut self.status)
+    }
+}
 
+/// Settings for the SDL window
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    pub scale: u32,                 // window pixels per low-resolution pixel
+    pub fullscreen: bool,
+    pub palette: Palette,
+    pub phosphor: Option<f32>,      // fraction of brightness pixels keep each frame after turning off
+    pub scaling: Scaling,
+    pub keymap: Keymap,
+    pub tone: ToneSettings,
+    pub muted: bool,                // whether sound starts muted; M toggles it
+    pub state_path: Option<String>, // where F5 and F9 save and load states
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        WindowOptions {
+            scale: SCALE,
+            fullscreen: false,
+            palette: Palette::default(),
+            phosphor: None,
+            scaling: Scaling::default(),
+            keymap: Keymap::default(),
+            tone: ToneSettings::default(),
+            muted: false,
+            state_path: None,
+        }
+    }
+}
 
-            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+fn sdl_error(err: impl ToString) -> Chip8Error {
+    Chip8Error::DisplayError(err.to_string())
+}
 
-            // The rest of the game loop goes here...
+/* Opens a window and runs ``machine`` in it until the user quits or the program halts.
+Messages for the user, such as save states being written, are passed to ``on_status`` */
+pub fn run(machine: &mut Machine, options: &WindowOptions, mut on_status: impl FnMut(Status) + 'static) -> Result<Exit> {
+    let map = InputMap::new(&options.keymap)?;
+    let sdl_context = sdl2::init().map_err(sdl_error)?;
+    let video_subsystem = sdl_context.video().map_err(sdl_error)?;
+    let width = WIDTH as u32 * options.scale;
+    let height = HEIGHT as u32 * options.scale;
 
-            let instr = cpu.fetch();
-            let opcode = cpu.decode(instr);
-            cpu.execute(opcode, keystate);
-            cpu.decr_timers();
-            draw_screen(&(cpu.vram), &mut canvas);
-            canvas.present();
-        };
+    let mut window = video_subsystem.window("Chip8-Crab", width, height);
+    window.position_centered().resizable();
+    if options.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().map_err(sdl_error)?;
+
+    // Presenting waits for vsync, so the renderer never draws more often than the screen refreshes
+    let canvas = window.into_canvas().present_vsync().build().map_err(sdl_error)?;
+    let texture_creator = canvas.texture_creator();
+    let mut display = SdlDisplay::new(canvas, &texture_creator)?
+        .with_palette(options.palette)
+        .with_scaling(options.scaling);
+    if let Some(decay) = options.phosphor {
+        display = display.with_phosphor(decay);
+    }
+    let controller_subsystem = sdl_context.game_controller().map_err(sdl_error)?;
+    let event_pump = sdl_context.event_pump().map_err(sdl_error)?;
+    let input = SdlInput::new(event_pump, controller_subsystem, map);
+
+    // Without an audio device the emulator still runs, silently
+    let audio_subsystem = sdl_context.audio().map_err(sdl_error)?;
+    let audio = SdlAudio::new(&audio_subsystem, options.tone, options.muted).unwrap_or_else(|err| {
+        on_status(Status::Error(err));
+        SdlAudio::silent()
     });
 
-    codes_ext
+    let mut frontend = Frontend::new(display, audio, input).with_status(on_status);
+    if let Some(path) = &options.state_path {
+        frontend = frontend.with_state_path(path);
+    }
+    frontend.run(machine)
 }
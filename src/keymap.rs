@@ -0,0 +1,208 @@
+//! Configurable mapping of keyboard keys to the 16 keys of the CHIP-8 keypad.
+//!
+//! A keymap file has one line per remapped keypad key, giving the hex key and the SDL names of
+//! the keys that press it, separated by commas. Game controller inputs are written ``Pad:`` and
+//! the SDL name of a button (``Pad:a``, ``Pad:dpup``) or an axis followed by the direction that
+//! presses the key (``Pad:leftx-``, ``Pad:righttrigger+``):
+//!
+//! ```text
+//! # Move with the arrow keys
+//! 5 = Up
+//! 7 = Left
+//! 8 = Down
+//! 9 = Right
+//! # How far sticks must move from the centre, out of 32767
+//! deadzone = 12000
+//!
+//! # Only applies when running pong.ch8
+//! [pong.ch8]
+//! 1 = Up, Keypad 8, Pad:dpup, Pad:lefty-
+//! 4 = Down, Keypad 2, Pad:dpdown, Pad:lefty+
+//! ```
+//!
+//! Key names are SDL scancode names, which name the physical position of a key on a US QWERTY
+//! keyboard whatever the active layout: ``A`` is the key right of Caps Lock, which is Q on an
+//! AZERTY keyboard. The default layout therefore uses the same block of keys on any keyboard and
+//! needs no remapping for AZERTY or QWERTZ.
+//!
+//! Keyboard and controller bindings are replaced separately: a line naming keyboard keys replaces
+//! the keyboard bindings of its key, and one naming controller inputs replaces its controller
+//! bindings, so remapping the keyboard keeps the controller defaults. Keys that are not mentioned
+//! keep the default layout. Lines after a ``[ROM]`` header only apply to the ROM with that file name, on
+//! top of the lines before the first header.
+
+use crate::error::*;
+use std::path::Path;
+
+// Prefix of game controller inputs
+pub const PAD_PREFIX: &str = "Pad:";
+// Default distance sticks must move from the centre before they press a key
+pub const DEFAULT_DEADZONE: i16 = 8000;
+
+// Default inputs in keypad order. The keyboard uses the 1234/QWER/ASDF/ZXCV block of a QWERTY
+// keyboard; controllers move with 5/7/8/9, like WASD, and press 6 and 4 with A and B
+const DEFAULT_KEYS: [&[&str]; 16] = [
+    &["X"],
+    &["1"],
+    &["2"],
+    &["3"],
+    &["Q", "Pad:b"],
+    &["W", "Pad:dpup", "Pad:lefty-"],
+    &["E", "Pad:a"],
+    &["A", "Pad:dpleft", "Pad:leftx-"],
+    &["S", "Pad:dpdown", "Pad:lefty+"],
+    &["D", "Pad:dpright", "Pad:leftx+"],
+    &["Z"],
+    &["C"],
+    &["4"],
+    &["R"],
+    &["F"],
+    &["V"],
+];
+
+/// The names of the keyboard keys and controller inputs bound to each keypad key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<String>; 16],
+    deadzone: i16,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            keys: DEFAULT_KEYS.map(|names| names.iter().map(|name| name.to_string()).collect()),
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+fn invalid(line: usize, reason: impl std::fmt::Display) -> Chip8Error {
+    Chip8Error::KeymapError(format!("line {line}: {reason}"))
+}
+
+impl Keymap {
+    /* Parses a keymap file, applying the overrides of the section for ``rom``, if any. Every
+    section is validated even when it doesn't apply */
+    pub fn parse(text: &str, rom: Option<&str>) -> Result<Keymap> {
+        let rom = rom.and_then(|rom| Path::new(rom).file_name()).and_then(|name| name.to_str());
+        let mut keymap = Keymap::default();
+        let mut applies = true;
+
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[') {
+                let section = section
+                    .strip_suffix(']')
+                    .ok_or_else(|| invalid(number, "unclosed section header"))?;
+                applies = rom == Some(section.trim());
+                continue;
+            }
+
+            let (key, names) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(number, format!("expected KEY = NAMES, got {line}")))?;
+            let key = key.trim();
+            if key.eq_ignore_ascii_case("deadzone") {
+                let deadzone = names
+                    .trim()
+                    .parse::<i16>()
+                    .ok()
+                    .filter(|deadzone| *deadzone >= 0)
+                    .ok_or_else(|| invalid(number, format!("invalid deadzone {}", names.trim())))?;
+                if applies {
+                    keymap.deadzone = deadzone;
+                }
+                continue;
+            }
+            let key = match u8::from_str_radix(key, 16) {
+                Ok(value) if key.len() == 1 => usize::from(value),
+                _ => return Err(invalid(number, format!("{key} is not a hex key from 0 to F"))),
+            };
+            let names: Vec<String> = names.split(',').map(|name| name.trim().to_string()).collect();
+            if names.iter().any(String::is_empty) {
+                return Err(invalid(number, "empty key name"));
+            }
+            if applies {
+                keymap.rebind(key, names);
+            }
+        }
+        Ok(keymap)
+    }
+
+    // Replaces the keyboard bindings of ``key`` if ``names`` has keyboard keys, and its controller
+    // bindings if it has controller inputs
+    fn rebind(&mut self, key: usize, names: Vec<String>) {
+        let is_pad = |name: &String| name.starts_with(PAD_PREFIX);
+        let (pads, keys): (Vec<String>, Vec<String>) = names.into_iter().partition(is_pad);
+        let (old_pads, old_keys): (Vec<String>, Vec<String>) =
+            std::mem::take(&mut self.keys[key]).into_iter().partition(is_pad);
+        let keys = if keys.is_empty() { old_keys } else { keys };
+        let pads = if pads.is_empty() { old_pads } else { pads };
+        self.keys[key] = keys.into_iter().chain(pads).collect();
+    }
+
+    /* Reads and parses the keymap file at ``path`` for ``rom`` */
+    pub fn load(path: &str, rom: Option<&str>) -> Result<Keymap> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| Chip8Error::KeymapError(format!("could not read {path}: {err}")))?;
+        Keymap::parse(&text, rom)
+    }
+
+    /* Returns the names of the keyboard keys and ``Pad:`` controller inputs bound to keypad key ``key`` */
+    pub fn names(&self, key: usize) -> &[String] {
+        &self.keys[key]
+    }
+
+    /* Returns how far an axis must move from the centre before it presses a key */
+    pub fn deadzone(&self) -> i16 {
+        self.deadzone
+    }
+}
+
+#[cfg(test)]
+pub mod keymap_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_keymap() {
+        let text = "\
+            # comment\n\
+            5 = Z\n\
+            [pong.ch8]\n\
+            1 = Up, Keypad 8\n\
+            [other.ch8]\n\
+            2 = Down\n\
+            deadzone = 100\n";
+        let keymap = Keymap::parse(text, Some("roms/pong.ch8")).unwrap();
+        assert_eq!(keymap.names(5), ["Z", "Pad:dpup", "Pad:lefty-"]);
+        assert_eq!(keymap.names(1), ["Up", "Keypad 8"]);
+        assert_eq!(keymap.names(2), ["2"]);
+        assert_eq!(keymap.names(0), ["X"]);
+        assert_eq!(keymap.names(6), ["E", "Pad:a"]);
+        assert_eq!(keymap.deadzone(), DEFAULT_DEADZONE);
+
+        let keymap = Keymap::parse(text, None).unwrap();
+        assert_eq!(keymap.names(1), ["1"]);
+    }
+
+    #[test]
+    pub fn test_keyboard_and_pad_are_remapped_separately() {
+        let keymap = Keymap::parse("5 = Up\n7 = Pad:x\n8 = K, Pad:y", None).unwrap();
+        assert_eq!(keymap.names(5), ["Up", "Pad:dpup", "Pad:lefty-"]);
+        assert_eq!(keymap.names(7), ["A", "Pad:x"]);
+        assert_eq!(keymap.names(8), ["K", "Pad:y"]);
+    }
+
+    #[test]
+    pub fn test_keymap_errors() {
+        assert!(Keymap::parse("G = A", None).is_err());
+        assert!(Keymap::parse("10 = A", None).is_err());
+        assert!(Keymap::parse("1 A", None).is_err());
+        assert!(Keymap::parse("1 = A,", None).is_err());
+        assert!(Keymap::parse("[pong.ch8", None).is_err());
+        assert!(Keymap::parse("deadzone = -1", None).is_err());
+    }
+}
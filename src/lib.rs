@@ -1,6 +1,29 @@
+pub mod assembler;
+pub mod audio;
 pub mod cpu;
+pub mod disasm;
+#[cfg(feature = "sdl")]
+pub mod display;
 pub mod error;
 pub mod font;
+pub mod framebuffer;
+pub mod frontend;
+pub mod image;
+pub mod keymap;
 pub mod loader;
+pub mod machine;
 pub mod memory;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+pub mod script;
+pub mod syntax;
+pub mod upscale;
+pub mod wav;
+#[cfg(feature = "sdl")]
 pub mod input;
+#[cfg(feature = "sdl")]
+pub mod sound;
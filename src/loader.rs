@@ -1,5 +1,6 @@
 use crate::cpu::{Opcode, CPU, NO_KEYS};
 use crate::error::*;
+use crate::quirks::Quirks;
 use std::fs;
 
 /// Accepts a binary file and returns a vector of bytes.
@@ -38,9 +39,14 @@ fn load_opcodes(filename: &str) -> Result<Vec<Opcode>> {
 /// Accepts a binary file corresponding to a series of
 /// raw u16 bytes to put into program memory. Returns a CPU with the ROM loaded.
 pub fn load_program(filename: &str) -> Result<CPU> {
+    load_program_with_quirks(filename, Quirks::default())
+}
+
+/// Like ``load_program``, but the returned CPU emulates the behaviour described by ``quirks``.
+pub fn load_program_with_quirks(filename: &str, quirks: Quirks) -> Result<CPU> {
     let bytes = load_bytes(filename)?;
-    let mut cpu = CPU::new();
-    cpu.load_program(&bytes);
+    let mut cpu = CPU::with_quirks(quirks);
+    cpu.load_program(&bytes)?;
     Ok(cpu)
 }
 
@@ -48,7 +54,7 @@ pub fn load_program(filename: &str) -> Result<CPU> {
 /// chip8 instruction. Executes each instruction in the CPU.
 pub fn run(filename: &str, cpu: &mut CPU) -> Result<()> {
     for opcode in load_opcodes(filename)? {
-        cpu.execute(opcode, NO_KEYS);
+        cpu.execute(opcode, NO_KEYS)?;
     }
     Ok(())
 }
@@ -0,0 +1,306 @@
+use crate::cpu::{KeyState, CPU};
+use crate::error::*;
+use crate::rewind::RewindBuffer;
+
+// Timers and the display run at 60Hz
+pub const FRAME_RATE: u32 = 60;
+// Instructions per second that suit most CHIP-8 games
+pub const DEFAULT_IPS: u32 = 700;
+
+/// Outcome of running the machine for one frame
+#[derive(Debug)]
+pub enum FrameResult {
+    /// Every cycle of the frame was executed
+    Completed,
+    /// The program exited through 00FD; the rest of the frame, and further frames, only tick the timers
+    Halted,
+    /// The CPU faulted; the frame was cut short without ticking the timers. The program counter
+    /// has usually moved past the faulting instruction, whose address is given in the error
+    Faulted(Chip8Error),
+}
+
+/// Drives a CPU in 60Hz frames: the instructions of one frame are executed,
+/// after which the delay and sound timers are ticked exactly once.
+/// Rates that are not a multiple of 60 are met on average by spreading the
+/// remainder over the frames, so 700 IPS runs 11 or 12 instructions per frame.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    cpu: CPU,
+    ips: u32,
+    cycles_per_frame: u32, // cycles to execute in the current frame
+    carry: u32, // instructions per second not yet spent on a frame, always below FRAME_RATE
+    cycle: u32, // cycles already executed in the current frame
+    sounding: bool, // whether the sound timer was running when the last frame ended, before it ticked
+    rewind: Option<RewindBuffer>, // snapshots taken at the start of each frame, if rewinding is enabled
+}
+
+impl Machine {
+    pub fn new(cpu: CPU) -> Self {
+        Machine::with_ips(cpu, DEFAULT_IPS)
+    }
+
+    /* Creates a machine executing ``ips`` instructions per second */
+    pub fn with_ips(cpu: CPU, ips: u32) -> Self {
+        let mut machine = Machine {
+            cpu,
+            ips: DEFAULT_IPS,
+            cycles_per_frame: 0,
+            carry: 0,
+            cycle: 0,
+            sounding: false,
+            rewind: None,
+        };
+        machine.set_ips(ips);
+        machine
+    }
+
+    /* Returns whether the tone should sound for the last frame. The sound timer has already been
+    ticked by then, so a timer set to N sounds for N frames rather than N - 1 */
+    pub fn sounding(&self) -> bool {
+        self.sounding
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    /* Returns the emulated instructions per second */
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    /* Sets the emulated instructions per second, which is at least 1. Below 60 IPS
+    some frames execute no instructions at all */
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips.max(1);
+        self.carry = 0;
+        self.budget_frame();
+        self.cycle = self.cycle.min(self.cycles_per_frame);
+    }
+
+    /* Returns the number of instructions executed in the current frame */
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    /* Starts recording a snapshot at the start of every frame, keeping the last ``frames`` of them */
+    pub fn enable_rewind(&mut self, frames: usize) {
+        self.rewind = Some(RewindBuffer::new(frames));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /* Restores the CPU to the start of the most recently recorded frame.
+    Returns false if rewinding is disabled or there is no more history */
+    pub fn rewind(&mut self) -> Result<bool> {
+        let Some(restored) = self.rewind.as_mut().and_then(|rewind| rewind.pop_into(&mut self.cpu)) else {
+            return Ok(false);
+        };
+        restored?;
+        self.cycle = 0;
+        self.sounding = false;
+        Ok(true)
+    }
+
+    /* Executes a single instruction, ticking the timers if it was the last one of the frame */
+    pub fn step(&mut self, keystate: KeyState) -> Result<()> {
+        // Let frames without instructions pass so there is one to execute
+        while self.cycles_per_frame == 0 {
+            self.record_snapshot();
+            self.end_frame();
+        }
+        self.record_snapshot();
+        self.cpu.step_with_keys(keystate)?;
+        self.cycle += 1;
+        if self.cycle >= self.cycles_per_frame {
+            self.end_frame();
+        }
+        Ok(())
+    }
+
+    /* Executes the remaining instructions of the current frame and ticks the timers */
+    pub fn run_frame(&mut self, keystate: KeyState) -> FrameResult {
+        self.record_snapshot();
+        while self.cycle < self.cycles_per_frame && !self.cpu.halted() {
+            if let Err(err) = self.cpu.step_with_keys(keystate) {
+                return FrameResult::Faulted(err);
+            }
+            self.cycle += 1;
+        }
+        self.end_frame();
+        if self.cpu.halted() {
+            FrameResult::Halted
+        } else {
+            FrameResult::Completed
+        }
+    }
+
+    fn record_snapshot(&mut self) {
+        if self.cycle == 0 {
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.push(&self.cpu);
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.sounding = self.cpu.beep > 0;
+        self.cpu.decr_timers();
+        self.cycle = 0;
+        self.budget_frame();
+    }
+
+    // Works out how many instructions the next frame executes, carrying the remainder forward
+    fn budget_frame(&mut self) {
+        let budget = self.ips + self.carry;
+        self.cycles_per_frame = budget / FRAME_RATE;
+        self.carry = budget % FRAME_RATE;
+    }
+}
+
+#[cfg(test)]
+pub mod machine_tests {
+    use super::*;
+    use crate::cpu::NO_KEYS;
+    use crate::random::random_tests::CountingRng;
+
+    fn machine(program: &[u8]) -> Machine {
+        let mut cpu = CPU::new();
+        cpu.load_program(program).unwrap();
+        Machine::new(cpu)
+    }
+
+    #[test]
+    pub fn test_cycles_per_frame() {
+        // Infinite loop of V0 += 1
+        let mut machine = machine(&[0x70, 0x01, 0x12, 0x00]);
+        machine.set_ips(600);
+        assert_eq!(machine.cycles_per_frame(), 10);
+        assert!(matches!(machine.run_frame(NO_KEYS), FrameResult::Completed));
+        assert_eq!(machine.cpu().vs[0], 5);
+    }
+
+    #[test]
+    pub fn test_fractional_ips() {
+        // Infinite loop of V0 += 1, so V0 counts every other instruction
+        let mut fast = machine(&[0x70, 0x01, 0x12, 0x00]);
+        fast.set_ips(90);
+        assert_eq!(fast.ips(), 90);
+        for _ in 0..FRAME_RATE {
+            fast.run_frame(NO_KEYS);
+        }
+        assert_eq!(fast.cpu().vs[0], 45);
+
+        let mut slow = machine(&[0x70, 0x01, 0x12, 0x00]);
+        slow.set_ips(30);
+        for _ in 0..FRAME_RATE {
+            slow.run_frame(NO_KEYS);
+        }
+        assert_eq!(slow.cpu().vs[0], 15);
+
+        assert_eq!(Machine::new(CPU::new()).ips(), DEFAULT_IPS);
+    }
+
+    #[test]
+    pub fn test_timers_tick_once_per_frame() {
+        // V0 = 10, delay = V0, loop forever
+        let mut machine = machine(&[0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04]);
+        machine.run_frame(NO_KEYS);
+        assert_eq!(machine.cpu().delay, 9);
+        machine.run_frame(NO_KEYS);
+        assert_eq!(machine.cpu().delay, 8);
+    }
+
+    #[test]
+    pub fn test_step_finishes_frame() {
+        let mut machine = machine(&[0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04]);
+        machine.set_ips(180);
+        for _ in 0..3 {
+            machine.step(NO_KEYS).unwrap();
+        }
+        assert_eq!(machine.cpu().delay, 9);
+    }
+
+    #[test]
+    pub fn test_halt_and_fault() {
+        let mut halting = machine(&[0x00, 0xFD]);
+        assert!(matches!(halting.run_frame(NO_KEYS), FrameResult::Halted));
+        assert!(matches!(halting.run_frame(NO_KEYS), FrameResult::Halted));
+
+        let mut faulting = machine(&[0x00, 0xEE]);
+        assert!(matches!(
+            faulting.run_frame(NO_KEYS),
+            FrameResult::Faulted(Chip8Error::RuntimeError { .. })
+        ));
+    }
+
+    #[test]
+    pub fn test_halting_frame_ticks_timers() {
+        // V0 = 10, delay = V0, exit
+        let mut machine = machine(&[0x60, 0x0A, 0xF0, 0x15, 0x00, 0xFD]);
+        assert!(matches!(machine.run_frame(NO_KEYS), FrameResult::Halted));
+        assert_eq!(machine.cpu().delay, 9);
+        assert!(matches!(machine.run_frame(NO_KEYS), FrameResult::Halted));
+        assert_eq!(machine.cpu().delay, 8);
+    }
+
+    #[test]
+    pub fn test_rewind() {
+        // Infinite loop of V0 += 1
+        let mut machine = machine(&[0x70, 0x01, 0x12, 0x00]);
+        machine.set_ips(600);
+        assert!(!machine.rewind().unwrap());
+
+        machine.enable_rewind(10);
+        for _ in 0..3 {
+            machine.run_frame(NO_KEYS);
+        }
+        assert_eq!(machine.cpu().vs[0], 15);
+        assert!(machine.rewind().unwrap());
+        assert_eq!(machine.cpu().vs[0], 10);
+        assert!(machine.rewind().unwrap());
+        assert!(machine.rewind().unwrap());
+        assert_eq!(machine.cpu().vs[0], 0);
+        assert!(!machine.rewind().unwrap());
+    }
+
+    #[test]
+    pub fn test_save_and_rewind_keep_random_source() {
+        // Loop of V0 = rand, one per frame
+        let mut machine = machine(&[0xC0, 0xFF, 0x12, 0x00]);
+        machine.set_ips(120);
+        machine.cpu_mut().set_rng(Box::new(CountingRng { next: 0 }));
+        machine.enable_rewind(10);
+        for _ in 0..3 {
+            machine.run_frame(NO_KEYS);
+        }
+        assert_eq!(machine.cpu().vs[0], 2);
+
+        let state = machine.cpu().save_state();
+        assert!(CPU::load_state(&state).is_err());
+        machine.run_frame(NO_KEYS);
+        machine.cpu_mut().restore_state(&state).unwrap();
+        machine.run_frame(NO_KEYS);
+        assert_eq!(machine.cpu().vs[0], 3);
+
+        assert!(machine.rewind().unwrap());
+        machine.run_frame(NO_KEYS);
+        assert_eq!(machine.cpu().vs[0], 3);
+        machine.run_frame(NO_KEYS);
+        assert_eq!(machine.cpu().vs[0], 4);
+    }
+}
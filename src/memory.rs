@@ -1,52 +1,92 @@
-use crate::font::FONT_TABLE;
-use ux::*;
-// CHIP-8 was commonly implemented on systems with 4 kB of memory, which we replicate here with an array of bytes
+use crate::error::*;
+use crate::font::{BIG_FONT_START, BIG_FONT_TABLE, FONT_TABLE};
+// CHIP-8 was commonly implemented on systems with 4 kB of memory, which we replicate here with an array of bytes.
+// XO-CHIP extends this to 64 kB, so the size is chosen when the memory is created
 
 const SIZE: usize = 4096;
 const PROGRAM_START: usize = 0x200;
 
 #[derive(Debug, Clone)]
 pub struct Memory {
-    mem: [u8; SIZE],
+    mem: Vec<u8>,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        let mut mem: [u8; SIZE] = [0; SIZE];
+        Memory::with_size(SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        let mut mem: Vec<u8> = vec![0; size];
         //Copy the contents of the font table into memory
         mem[0..FONT_TABLE.len()].copy_from_slice(&FONT_TABLE);
-        Memory { mem: mem }
+        let big_font = usize::from(BIG_FONT_START);
+        mem[big_font..big_font + BIG_FONT_TABLE.len()].copy_from_slice(&BIG_FONT_TABLE);
+        Memory { mem }
+    }
+
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// Wraps raw memory contents, e.g. restored from a save state
+    pub fn from_bytes(mem: Vec<u8>) -> Self {
+        Memory { mem }
+    }
+
+    /// Grows or truncates memory to ``size`` bytes, zero-filling any new bytes
+    pub fn resize(&mut self, size: usize) {
+        self.mem.resize(size, 0);
     }
 
-    pub fn read_word(&self, addr: u12) -> u16 {
-        let wide: u16 = u12::into(addr);
-        let index: usize = u16::into(wide);
+    fn out_of_range(&self, index: usize) -> Fault {
+        Fault::MemoryOutOfRange {
+            addr: index,
+            size: self.mem.len(),
+        }
+    }
+
+    pub fn read_word(&self, addr: u16) -> std::result::Result<u16, Fault> {
+        let index: usize = usize::from(addr);
         let mut line: u16 = 0;
         for i in 0..2 {
+            let byte = self.mem.get(index + i).ok_or(self.out_of_range(index + i))?;
             line = line << 8;
-            line = line + u16::from(self.mem[index + i]);
+            line = line + u16::from(*byte);
         }
-        line
+        Ok(line)
     }
 
-    pub fn read(&self, addr: u12) -> u8 {
-        let wide: u16 = u12::into(addr);
-        let index: usize = u16::into(wide);
-        self.mem[index]
+    pub fn read(&self, addr: u16) -> std::result::Result<u8, Fault> {
+        let index: usize = usize::from(addr);
+        self.mem.get(index).copied().ok_or(self.out_of_range(index))
     }
 
-    pub fn write(&mut self, addr: u12, value: u8) -> () {
-        let wide: u16 = u12::into(addr);
-        let index: usize = u16::into(wide);
-        self.mem[index] = value;
+    pub fn write(&mut self, addr: u16, value: u8) -> std::result::Result<(), Fault> {
+        let index: usize = usize::from(addr);
+        let fault = self.out_of_range(index);
+        let byte = self.mem.get_mut(index).ok_or(fault)?;
+        *byte = value;
+        Ok(())
     }
 
-    pub fn load_program(&mut self, data: &[u8]) -> () {
+    pub fn load_program(&mut self, data: &[u8]) -> Result<()> {
         let index = PROGRAM_START;
-        assert!(
-            index + data.len() <= (SIZE - PROGRAM_START),
-            "program too large"
-        );
+        if index + data.len() > self.mem.len() {
+            return Err(Chip8Error::ROMLoaderError {
+                reason: format!(
+                    "program of {} bytes does not fit in the {} bytes of memory after {:#X}",
+                    data.len(),
+                    self.mem.len() - index,
+                    index
+                ),
+            });
+        }
         self.mem[index..index + data.len()].copy_from_slice(data);
+        Ok(())
     }
 }
@@ -0,0 +1,209 @@
+//! Colour palettes for the display.
+//!
+//! A palette gives the colour of each pixel value: off, lit in the first plane, lit in XO-CHIP's
+//! second plane and lit in both. Palettes are chosen by name or written as comma separated
+//! ``RRGGBB`` colours; with only a background and a foreground, the colours of the other planes
+//! are blended from the two.
+
+use crate::error::*;
+use std::str::FromStr;
+
+pub type Rgb = [u8; 3];
+
+/// The colour of each pixel value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [Rgb; 4],
+}
+
+// Name given to palettes parsed from colours
+const CUSTOM: &str = "custom";
+
+/// The built-in palettes, in the order the palette hotkey cycles through them
+pub const PALETTES: [Palette; 5] = [
+    Palette {
+        name: "classic",
+        colors: [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]],
+    },
+    Palette {
+        name: "amber",
+        colors: [[0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00], [0xB3, 0x6B, 0x00], [0x66, 0x3D, 0x00]],
+    },
+    Palette {
+        name: "green",
+        colors: [[0x00, 0x1A, 0x00], [0x33, 0xFF, 0x33], [0x1F, 0xA0, 0x1F], [0x0F, 0x5A, 0x0F]],
+    },
+    // The Game Boy's four greens, spaced evenly enough that every plane stands out from the background
+    Palette {
+        name: "lcd",
+        colors: [[0xE0, 0xF8, 0xD0], [0x08, 0x18, 0x20], [0x34, 0x68, 0x56], [0x88, 0xC0, 0x70]],
+    },
+    Palette {
+        name: "high-contrast",
+        colors: [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xFF, 0xFF, 0x00], [0x00, 0xFF, 0xFF]],
+    },
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        PALETTES[0]
+    }
+}
+
+impl Palette {
+    /* Returns the built-in palette called ``name``, ignoring case */
+    pub fn named(name: &str) -> Option<Palette> {
+        let name = match name.to_ascii_lowercase().as_str() {
+            "green-phosphor" | "phosphor" => "green".to_string(),
+            name => name.to_string(),
+        };
+        PALETTES.iter().find(|palette| palette.name == name).copied()
+    }
+
+    /* Returns the colour of ``pixel``, whose bit n is set when it is lit in plane n */
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors[usize::from(pixel & 0b11)]
+    }
+}
+
+/// The palettes the palette hotkey cycles through: the built-in ones, followed by the user's own
+/// palette if it isn't one of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteCycle {
+    palettes: Vec<Palette>,
+    index: usize,
+}
+
+impl PaletteCycle {
+    /* Starts the cycle at ``palette`` */
+    pub fn new(palette: Palette) -> Self {
+        let mut palettes = PALETTES.to_vec();
+        let index = match palettes.iter().position(|builtin| *builtin == palette) {
+            Some(index) => index,
+            None => {
+                palettes.push(palette);
+                palettes.len() - 1
+            }
+        };
+        PaletteCycle { palettes, index }
+    }
+
+    pub fn current(&self) -> Palette {
+        self.palettes[self.index]
+    }
+
+    /* Moves on to the next palette, wrapping around, and returns it */
+    pub fn advance(&mut self) -> Palette {
+        self.index = (self.index + 1) % self.palettes.len();
+        self.current()
+    }
+}
+
+impl Default for PaletteCycle {
+    fn default() -> Self {
+        PaletteCycle::new(Palette::default())
+    }
+}
+
+fn parse_color(hex: &str) -> Result<Rgb> {
+    let hex = hex.trim().trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| Chip8Error::CommandParseError(format!("invalid colour {hex}")))?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok([r, g, b])
+}
+
+// Mixes ``weight`` thirds of ``foreground`` into ``background``
+fn blend(background: Rgb, foreground: Rgb, weight: u16) -> Rgb {
+    let mut color = background;
+    for (channel, fg) in color.iter_mut().zip(foreground) {
+        *channel = ((u16::from(*channel) * (3 - weight) + u16::from(fg) * weight) / 3) as u8;
+    }
+    color
+}
+
+/* Parses the name of a built-in palette, or comma separated RRGGBB colours: background and
+foreground, optionally followed by the colours of the second plane and of both planes */
+impl FromStr for Palette {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<Palette> {
+        if let Some(palette) = Palette::named(s.trim()) {
+            return Ok(palette);
+        }
+        if !s.contains(',') {
+            let names: Vec<&str> = PALETTES.iter().map(|palette| palette.name).collect();
+            return Err(Chip8Error::CommandParseError(format!(
+                "unknown palette {s}, expected one of {} or a list of colours",
+                names.join(", ")
+            )));
+        }
+
+        let colors = s.split(',').map(parse_color).collect::<Result<Vec<Rgb>>>()?;
+        let colors = match colors[..] {
+            [background, foreground] => [
+                background,
+                foreground,
+                blend(background, foreground, 2),
+                blend(background, foreground, 1),
+            ],
+            [off, first, second, both] => [off, first, second, both],
+            _ => {
+                return Err(Chip8Error::CommandParseError(format!(
+                    "a palette needs 2 or 4 colours, not {}",
+                    colors.len()
+                )))
+            }
+        };
+        Ok(Palette { name: CUSTOM, colors })
+    }
+}
+
+#[cfg(test)]
+pub mod palette_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_palette() {
+        assert_eq!("Amber".parse::<Palette>().unwrap().name, "amber");
+        assert_eq!("green-phosphor".parse::<Palette>().unwrap().name, "green");
+
+        let palette: Palette = "#000000,ffffff".parse().unwrap();
+        assert_eq!(palette.color(1), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(palette.color(2), [0xAA, 0xAA, 0xAA]);
+        assert_eq!(palette.color(3), [0x55, 0x55, 0x55]);
+
+        assert!("sepia".parse::<Palette>().is_err());
+        assert!("000000,fffff".parse::<Palette>().is_err());
+        assert!("000000,ffffff,ffffff".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    pub fn test_colors_stand_out_from_background() {
+        for palette in PALETTES {
+            let [background, lit @ ..] = palette.colors;
+            for color in lit {
+                let contrast = color.iter().zip(background).map(|(a, b)| a.abs_diff(b)).max().unwrap();
+                assert!(contrast >= 0x40, "{} has a colour too close to its background", palette.name);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_cycle_palettes() {
+        let mut cycle = PaletteCycle::default();
+        for expected in PALETTES.iter().skip(1).chain(&PALETTES[..1]) {
+            assert_eq!(cycle.advance(), *expected);
+        }
+
+        // A custom palette comes around again after the built-in ones
+        let custom: Palette = "102030,c0d0e0".parse().unwrap();
+        let mut cycle = PaletteCycle::new(custom);
+        for expected in PALETTES.iter().chain([&custom]) {
+            assert_eq!(cycle.advance(), *expected);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Phosphor persistence: an optional post-process that fades pixels out over a few frames
+//! instead of turning them off at once, like the phosphor of a CRT.
+//!
+//! CHIP-8 programs erase and redraw sprites with XOR, so moving sprites are often only lit
+//! every other frame and flicker badly. Blending each pixel's brightness with the previous
+//! frames hides the flicker without touching ``vram``.
+
+use crate::framebuffer::{Framebuffer, PLANES};
+use crate::palette::{Palette, Rgb};
+
+/// How brightly every pixel of each plane is glowing, from 0 to 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phosphor {
+    decay: f32, // fraction of a pixel's brightness left one frame after it turns off
+    width: usize,
+    height: usize,
+    glow: Vec<[f32; PLANES]>,
+}
+
+impl Phosphor {
+    /* Creates the post-process with ``decay`` clamped to 0 to 1. A decay of 0 turns pixels
+    off at once, higher values make them fade for longer */
+    pub fn new(decay: f32) -> Self {
+        Phosphor {
+            decay: decay.clamp(0.0, 1.0),
+            width: 0,
+            height: 0,
+            glow: Vec::new(),
+        }
+    }
+
+    /* Advances one frame: lit pixels glow fully and unlit pixels fade. Changing resolution
+    starts over from a dark screen */
+    pub fn update(&mut self, vram: &Framebuffer) {
+        if (self.width, self.height) != (vram.width(), vram.height()) {
+            self.width = vram.width();
+            self.height = vram.height();
+            self.glow = vec![[0.0; PLANES]; self.width * self.height];
+        }
+        for (y, row) in vram.rows().enumerate() {
+            for x in 0..self.width {
+                let pixel = row.get(x);
+                let glow = &mut self.glow[y * self.width + x];
+                for (plane, brightness) in glow.iter_mut().enumerate() {
+                    if pixel & (1 << plane) != 0 {
+                        *brightness = 1.0;
+                    } else {
+                        *brightness *= self.decay;
+                    }
+                }
+            }
+        }
+    }
+
+    /* Returns the colour of the pixel at (x, y), mixing the palette's colours by how brightly
+    each plane glows */
+    pub fn color(&self, x: usize, y: usize, palette: &Palette) -> Rgb {
+        let [first, second] = self.glow[y * self.width + x];
+        let weights = [
+            (1.0 - first) * (1.0 - second),
+            first * (1.0 - second),
+            (1.0 - first) * second,
+            first * second,
+        ];
+        let mut color = [0.0f32; 3];
+        for (weight, palette_color) in weights.iter().zip(palette.colors) {
+            for (channel, value) in color.iter_mut().zip(palette_color) {
+                *channel += weight * f32::from(value);
+            }
+        }
+        color.map(|channel| channel.round() as u8)
+    }
+}
+
+#[cfg(test)]
+pub mod phosphor_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_pixels_fade() {
+        let palette = Palette::default();
+        let mut vram = Framebuffer::new(64, 32);
+        let mut phosphor = Phosphor::new(0.5);
+        vram.set(3, 4, 1);
+        phosphor.update(&vram);
+        assert_eq!(phosphor.color(3, 4, &palette), [255, 255, 255]);
+        assert_eq!(phosphor.color(0, 0, &palette), [0, 0, 0]);
+
+        vram.set(3, 4, 0);
+        phosphor.update(&vram);
+        assert_eq!(phosphor.color(3, 4, &palette), [128, 128, 128]);
+        phosphor.update(&vram);
+        assert_eq!(phosphor.color(3, 4, &palette), [64, 64, 64]);
+
+        // Without decay, pixels switch off at once
+        let mut phosphor = Phosphor::new(0.0);
+        vram.set(3, 4, 0b11);
+        phosphor.update(&vram);
+        assert_eq!(phosphor.color(3, 4, &palette), palette.color(0b11));
+        vram.set(3, 4, 0b10);
+        phosphor.update(&vram);
+        assert_eq!(phosphor.color(3, 4, &palette), palette.color(0b10));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::error::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Behavioural differences between the CHIP-8 interpreters that ROMs were written for.
+/// Each flag selects between the original COSMAC VIP behaviour and a later variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing past the last register stored or loaded
+    pub load_store_increments_i: bool,
+    /// BNNN is read as BXNN and jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60Hz frame before drawing
+    pub display_wait: bool,
+    /// Memory spans the 64 KiB XO-CHIP address space instead of 4 KiB
+    pub extended_memory: bool,
+}
+
+/// Named quirk profiles matching the platforms most ROMs target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// The original COSMAC VIP interpreter
+    Chip8,
+    /// The HP-48 interpreter, which introduced the "modern" shift and load/store behaviour
+    Chip48,
+    /// SUPER-CHIP 1.1 on the HP-48
+    SuperChip,
+    /// Octo's XO-CHIP extension
+    XoChip,
+}
+
+impl Quirks {
+    pub const CHIP8: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+        extended_memory: false,
+    };
+
+    pub const CHIP48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        extended_memory: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        extended_memory: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        extended_memory: true,
+    };
+
+    /* Returns the quirks used by ``platform`` */
+    pub fn preset(platform: Platform) -> Self {
+        match platform {
+            Platform::Chip8 => Quirks::CHIP8,
+            Platform::Chip48 => Quirks::CHIP48,
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// The behaviour the emulator has always used: shifts and load/store act on VX alone,
+    /// BNNN is read as BXNN, and sprites are clipped without waiting for the display
+    fn default() -> Self {
+        Quirks::SUPER_CHIP
+    }
+}
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [
+        Platform::Chip8,
+        Platform::Chip48,
+        Platform::SuperChip,
+        Platform::XoChip,
+    ];
+
+    pub fn quirks(&self) -> Quirks {
+        Quirks::preset(*self)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<Platform> {
+        match s.trim().to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "chip8" | "vip" | "cosmac" => Ok(Platform::Chip8),
+            "chip48" => Ok(Platform::Chip48),
+            "schip" | "superchip" | "schip11" => Ok(Platform::SuperChip),
+            "xochip" | "octo" => Ok(Platform::XoChip),
+            _ => Err(Chip8Error::UnknownPlatform(s.to_string())),
+        }
+    }
+}
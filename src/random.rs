@@ -0,0 +1,154 @@
+use crate::error::*;
+use rand::{thread_rng, Rng};
+use std::fmt::Debug;
+
+/// A source of random bytes for CXNN. Implementations must be able to serialize their
+/// state so that save states reproduce the same sequence of random numbers.
+pub trait RandomSource: Debug + Send {
+    /// Returns the next random byte
+    fn next_u8(&mut self) -> u8;
+
+    /// Resets the generator so that it produces the sequence determined by ``seed``
+    fn seed(&mut self, seed: u64);
+
+    /// Returns the internal state of the generator
+    fn state(&self) -> Vec<u8>;
+
+    /// Restores a state previously returned by ``state``
+    fn restore(&mut self, state: &[u8]) -> Result<()>;
+
+    /// Identifies the kind of generator, so that a save state is only restored into one that can read it
+    fn name(&self) -> &str;
+
+    fn box_clone(&self) -> Box<dyn RandomSource>;
+}
+
+impl Clone for Box<dyn RandomSource> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// The default generator: xorshift64*, which is fast, small and good enough for games
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorShiftRng {
+    state: u64,
+}
+
+impl XorShiftRng {
+    pub fn from_seed(seed: u64) -> Self {
+        let mut rng = XorShiftRng { state: 0 };
+        rng.seed(seed);
+        rng
+    }
+
+    /* Creates a generator seeded from the operating system, for runs that needn't be reproducible */
+    pub fn from_entropy() -> Self {
+        XorShiftRng::from_seed(thread_rng().gen())
+    }
+}
+
+impl RandomSource for XorShiftRng {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn seed(&mut self, seed: u64) {
+        // xorshift gets stuck at zero, so mix the seed through splitmix64 first
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        self.state = if z == 0 { 1 } else { z };
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<()> {
+        let bytes: [u8; 8] = state.try_into().map_err(|_| {
+            Chip8Error::SaveStateError(format!("random state has {} bytes, expected 8", state.len()))
+        })?;
+        let state = u64::from_be_bytes(bytes);
+        if state == 0 {
+            return Err(Chip8Error::SaveStateError("random state is zero".to_string()));
+        }
+        self.state = state;
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "xorshift64*"
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+pub mod random_tests {
+    use super::*;
+
+    // Returns 0, 1, 2, ... so that the generator in use can be told apart from the default one
+    #[derive(Debug, Clone)]
+    pub struct CountingRng {
+        pub next: u8,
+    }
+
+    impl RandomSource for CountingRng {
+        fn next_u8(&mut self) -> u8 {
+            self.next = self.next.wrapping_add(1);
+            self.next - 1
+        }
+
+        fn seed(&mut self, seed: u64) {
+            self.next = seed as u8;
+        }
+
+        fn state(&self) -> Vec<u8> {
+            vec![self.next]
+        }
+
+        fn restore(&mut self, state: &[u8]) -> Result<()> {
+            self.next = state[0];
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn box_clone(&self) -> Box<dyn RandomSource> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    pub fn test_same_seed_same_sequence() {
+        let mut a = XorShiftRng::from_seed(1234);
+        let mut b = XorShiftRng::from_seed(1234);
+        let a: Vec<u8> = (0..64).map(|_| a.next_u8()).collect();
+        let b: Vec<u8> = (0..64).map(|_| b.next_u8()).collect();
+        assert_eq!(a, b);
+
+        let mut c = XorShiftRng::from_seed(1235);
+        let c: Vec<u8> = (0..64).map(|_| c.next_u8()).collect();
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    pub fn test_restore_state() {
+        let mut rng = XorShiftRng::from_seed(0);
+        rng.next_u8();
+        let mut restored = XorShiftRng::from_seed(99);
+        restored.restore(&rng.state()).unwrap();
+        assert_eq!(restored.next_u8(), rng.next_u8());
+        assert!(restored.restore(&[0; 8]).is_err());
+        assert!(restored.restore(&[1; 3]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::cpu::CPU;
+use crate::error::*;
+use std::collections::VecDeque;
+
+// Frames of history kept by default, 10 seconds at 60Hz
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+
+/// Encodes one snapshot relative to the next one. The bytes that differ between the two save
+/// states are stored as runs of XORed literals separated by runs of unchanged bytes, which keeps
+/// consecutive frames (where little of memory or vram changes) down to a handful of bytes.
+#[derive(Debug, Clone)]
+struct Delta {
+    len: usize,    // length of the snapshot this delta reconstructs
+    runs: Vec<u8>, // pairs of (unchanged length, literal length) varints, each followed by the literals
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+impl Delta {
+    /* Computes the delta that turns ``from`` into ``to`` */
+    fn between(from: &[u8], to: &[u8]) -> Delta {
+        let len = from.len().max(to.len());
+        let xor = |i: usize| from.get(i).copied().unwrap_or(0) ^ to.get(i).copied().unwrap_or(0);
+
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < len {
+            let unchanged_start = i;
+            while i < len && xor(i) == 0 {
+                i += 1;
+            }
+            let literal_start = i;
+            while i < len && xor(i) != 0 {
+                i += 1;
+            }
+            write_varint(&mut runs, literal_start - unchanged_start);
+            write_varint(&mut runs, i - literal_start);
+            runs.extend((literal_start..i).map(xor));
+        }
+        Delta { len: to.len(), runs }
+    }
+
+    /* Reconstructs the snapshot this delta was computed towards from ``from`` */
+    fn apply(&self, from: &[u8]) -> Vec<u8> {
+        let mut data = from.to_vec();
+        data.resize(data.len().max(self.len), 0);
+
+        let mut i = 0;
+        let mut pos = 0;
+        while pos < self.runs.len() {
+            i += read_varint(&self.runs, &mut pos);
+            let literals = read_varint(&self.runs, &mut pos);
+            for byte in &self.runs[pos..pos + literals] {
+                data[i] ^= byte;
+                i += 1;
+            }
+            pos += literals;
+        }
+        data.truncate(self.len);
+        data
+    }
+}
+
+/// A bounded history of CPU snapshots for stepping backwards in time.
+///
+/// Only the most recent snapshot is kept in full. Each older snapshot is stored as a delta
+/// against the snapshot that followed it, so rewinding walks the chain backwards one delta at a time
+/// and dropping the oldest snapshot is simply dropping its delta.
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>, // deltas.back() turns ``newest`` into the snapshot before it
+}
+
+impl RewindBuffer {
+    /* Creates a buffer holding at most ``capacity`` snapshots */
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /* Returns the number of snapshots that can currently be rewound to */
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /* Returns the approximate number of bytes used by the stored snapshots */
+    pub fn memory_usage(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, Vec::len);
+        newest + self.deltas.iter().map(|delta| delta.runs.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    /* Records a snapshot of ``cpu``, discarding the oldest snapshot if the buffer is full */
+    pub fn push(&mut self, cpu: &CPU) {
+        let state = cpu.save_state();
+        if let Some(previous) = self.newest.take() {
+            self.deltas.push_back(Delta::between(&state, &previous));
+        }
+        self.newest = Some(state);
+        if self.deltas.len() >= self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /* Removes and returns the most recent snapshot */
+    pub fn pop(&mut self) -> Option<Result<CPU>> {
+        self.pop_state().map(|state| CPU::load_state(&state))
+    }
+
+    /* Removes the most recent snapshot and restores it into ``cpu``, keeping its random number generator */
+    pub fn pop_into(&mut self, cpu: &mut CPU) -> Option<Result<()>> {
+        self.pop_state().map(|state| cpu.restore_state(&state))
+    }
+
+    fn pop_state(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.newest = self.deltas.pop_back().map(|delta| delta.apply(&newest));
+        Some(newest)
+    }
+}
+
+#[cfg(test)]
+pub mod rewind_tests {
+    use super::*;
+
+    fn counting_cpu() -> CPU {
+        // V0 += 1 forever
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        cpu
+    }
+
+    #[test]
+    pub fn test_delta_round_trip() {
+        let from = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let to = vec![1, 2, 9, 4, 5, 6, 0, 0, 10, 11];
+        assert_eq!(Delta::between(&from, &to).apply(&from), to);
+        assert_eq!(Delta::between(&to, &from).apply(&to), from);
+        assert_eq!(Delta::between(&from, &from).apply(&from), from);
+    }
+
+    #[test]
+    pub fn test_rewinds_in_reverse_order() {
+        let mut cpu = counting_cpu();
+        let mut buffer = RewindBuffer::new(100);
+        for _ in 0..10 {
+            buffer.push(&cpu);
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+        }
+        assert_eq!(buffer.len(), 10);
+        for expected in (0..10).rev() {
+            let restored = buffer.pop().unwrap().unwrap();
+            assert_eq!(restored.vs[0], expected);
+        }
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    pub fn test_capacity_drops_oldest() {
+        let mut cpu = counting_cpu();
+        let mut buffer = RewindBuffer::new(3);
+        for _ in 0..10 {
+            buffer.push(&cpu);
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+        }
+        assert_eq!(buffer.len(), 3);
+        let oldest = std::iter::from_fn(|| buffer.pop()).last().unwrap().unwrap();
+        assert_eq!(oldest.vs[0], 7);
+    }
+
+    #[test]
+    pub fn test_deltas_are_compact() {
+        let mut cpu = counting_cpu();
+        let mut buffer = RewindBuffer::new(100);
+        buffer.push(&cpu);
+        let full = buffer.memory_usage();
+        for _ in 0..10 {
+            cpu.step().unwrap();
+            buffer.push(&cpu);
+        }
+        assert!(buffer.memory_usage() < full + 10 * 32);
+    }
+}
@@ -0,0 +1,493 @@
+//! Save states: a versioned binary snapshot of everything needed to resume a CPU.
+//!
+//! A save state starts with the magic bytes ``CH8S`` and a big-endian ``u16`` format version,
+//! followed by a sequence of chunks. Each chunk is a four byte tag, a big-endian ``u32`` length
+//! and that many bytes of payload. Loading skips chunks with unknown tags, so newer versions can
+//! add chunks without breaking older readers.
+
+use crate::cpu::{Framebuffer, CPU, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, PLANES, WIDTH};
+use crate::error::*;
+use crate::memory::Memory;
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, XorShiftRng};
+use std::fs;
+
+const MAGIC: &[u8; 4] = b"CH8S";
+pub const VERSION: u16 = 1;
+
+const QUIRKS: &[u8; 4] = b"QIRK";
+const REGISTERS: &[u8; 4] = b"REGS";
+const STACK: &[u8; 4] = b"STAK";
+const MEMORY: &[u8; 4] = b"MEMO";
+const VRAM: &[u8; 4] = b"VRAM";
+const RPL_FLAGS: &[u8; 4] = b"RPLF";
+const AUDIO: &[u8; 4] = b"AUDI";
+const RANDOM_SOURCE: &[u8; 4] = b"RNGK";
+const RANDOM: &[u8; 4] = b"RAND";
+
+// Memory sizes a save state may contain
+const MEMORY_SIZES: [usize; 2] = [0x1000, 0x10000];
+
+fn invalid(reason: impl Into<String>) -> Chip8Error {
+    Chip8Error::SaveStateError(reason.into())
+}
+
+/// Appends tagged chunks to a save state
+struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.extend(VERSION.to_be_bytes());
+        StateWriter { data }
+    }
+
+    fn chunk(&mut self, tag: &[u8; 4], payload: &[u8]) {
+        self.data.extend(tag);
+        self.data.extend((payload.len() as u32).to_be_bytes());
+        self.data.extend(payload);
+    }
+}
+
+/// Reads the payload of a single chunk
+struct ChunkReader<'a> {
+    tag: &'a [u8; 4],
+    data: &'a [u8],
+}
+
+impl<'a> ChunkReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid(format!(
+                "chunk {} ended early",
+                String::from_utf8_lossy(self.tag)
+            )));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(invalid(format!("{other} is not a boolean"))),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    // Chunks must be consumed exactly, so that a corrupted length is caught
+    fn finish(self) -> Result<()> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "chunk {} has {} unexpected trailing bytes",
+                String::from_utf8_lossy(self.tag),
+                self.data.len()
+            )))
+        }
+    }
+}
+
+/// Splits a save state into its chunks, checking the header
+fn read_chunks(data: &[u8]) -> Result<Vec<ChunkReader<'_>>> {
+    if data.len() < 6 || &data[0..4] != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    let version = u16::from_be_bytes([data[4], data[5]]);
+    if version == 0 || version > VERSION {
+        return Err(invalid(format!(
+            "version {version} is not supported, expected at most {VERSION}"
+        )));
+    }
+
+    let mut chunks = Vec::new();
+    let mut rest = &data[6..];
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Err(invalid("truncated chunk header"));
+        }
+        let tag: &[u8; 4] = rest[0..4].try_into().unwrap();
+        let len = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        if rest.len() - 8 < len {
+            return Err(invalid(format!(
+                "chunk {} is truncated",
+                String::from_utf8_lossy(tag)
+            )));
+        }
+        chunks.push(ChunkReader {
+            tag,
+            data: &rest[8..8 + len],
+        });
+        rest = &rest[8 + len..];
+    }
+    Ok(chunks)
+}
+
+impl CPU {
+    /* Serializes the full state of the CPU into a save state */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        let quirks = self.quirks;
+        writer.chunk(
+            QUIRKS,
+            &[
+                quirks.shift_uses_vy,
+                quirks.load_store_increments_i,
+                quirks.jump_uses_vx,
+                quirks.vf_reset,
+                quirks.clip_sprites,
+                quirks.display_wait,
+                quirks.extended_memory,
+            ]
+            .map(u8::from),
+        );
+
+        let mut registers = self.vs.to_vec();
+        registers.extend(self.pc.to_be_bytes());
+        registers.extend(self.index.to_be_bytes());
+        registers.extend([
+            self.delay,
+            self.beep,
+            u8::from(self.hires),
+            self.planes,
+            u8::from(self.vblank),
+            u8::from(self.halted),
+        ]);
+        writer.chunk(REGISTERS, &registers);
+
+        let mut stack = (self.stack_limit as u32).to_be_bytes().to_vec();
+        for addr in &self.stack {
+            stack.extend(addr.to_be_bytes());
+        }
+        writer.chunk(STACK, &stack);
+
+        writer.chunk(MEMORY, self.ram.bytes());
+
+        // Always stored at the high-resolution size in column-major order, padded with zeros
+        let mut vram = Vec::with_capacity(HIRES_WIDTH * HIRES_HEIGHT);
+        for x in 0..HIRES_WIDTH {
+            for y in 0..HIRES_HEIGHT {
+                let inside = x < self.vram.width() && y < self.vram.height();
+                vram.push(if inside { self.vram.get(x, y) } else { 0 });
+            }
+        }
+        writer.chunk(VRAM, &vram);
+
+        writer.chunk(RPL_FLAGS, &self.rpl);
+
+        let mut audio = self.audio_pattern.to_vec();
+        audio.push(self.pitch);
+        writer.chunk(AUDIO, &audio);
+
+        writer.chunk(RANDOM_SOURCE, self.rng.name().as_bytes());
+        writer.chunk(RANDOM, &self.rng.state());
+
+        writer.data
+    }
+
+    /* Restores a CPU from a save state produced by ``save_state``, validating it first.
+    The random state is restored into the default generator */
+    pub fn load_state(data: &[u8]) -> Result<CPU> {
+        CPU::load_state_with_rng(data, Box::new(XorShiftRng::from_entropy()))
+    }
+
+    /* Restores a save state into this CPU, keeping its random number generator.
+    The CPU is left untouched if the state is invalid */
+    pub fn restore_state(&mut self, data: &[u8]) -> Result<()> {
+        *self = CPU::load_state_with_rng(data, self.rng.clone())?;
+        Ok(())
+    }
+
+    /* Restores a CPU like ``load_state``, restoring the random state into ``rng``,
+    which must be the same kind of generator the state was saved with */
+    pub fn load_state_with_rng(data: &[u8], rng: Box<dyn RandomSource>) -> Result<CPU> {
+        let mut cpu = CPU::new();
+        cpu.rng = rng;
+        let mut seen: Vec<&[u8; 4]> = Vec::new();
+        let mut vram: &[u8] = &[];
+        let mut random: Option<&[u8]> = None;
+
+        for mut chunk in read_chunks(data)? {
+            if seen.contains(&chunk.tag) {
+                return Err(invalid(format!(
+                    "chunk {} appears more than once",
+                    String::from_utf8_lossy(chunk.tag)
+                )));
+            }
+            match chunk.tag {
+                QUIRKS => {
+                    cpu.quirks = Quirks {
+                        shift_uses_vy: chunk.bool()?,
+                        load_store_increments_i: chunk.bool()?,
+                        jump_uses_vx: chunk.bool()?,
+                        vf_reset: chunk.bool()?,
+                        clip_sprites: chunk.bool()?,
+                        display_wait: chunk.bool()?,
+                        extended_memory: chunk.bool()?,
+                    };
+                }
+                REGISTERS => {
+                    cpu.vs = chunk.array()?;
+                    cpu.pc = chunk.u16()?;
+                    cpu.index = chunk.u16()?;
+                    cpu.delay = chunk.u8()?;
+                    cpu.beep = chunk.u8()?;
+                    cpu.hires = chunk.bool()?;
+                    cpu.planes = chunk.u8()?;
+                    cpu.vblank = chunk.bool()?;
+                    cpu.halted = chunk.bool()?;
+                    if usize::from(cpu.planes) >= 1 << PLANES {
+                        return Err(invalid(format!("invalid plane mask {:#b}", cpu.planes)));
+                    }
+                }
+                STACK => {
+                    cpu.stack_limit = chunk.u32()? as usize;
+                    cpu.stack = Vec::new();
+                    while !chunk.data.is_empty() {
+                        cpu.stack.push(chunk.u16()?);
+                    }
+                }
+                MEMORY => {
+                    let mem = chunk.take(chunk.data.len())?;
+                    if !MEMORY_SIZES.contains(&mem.len()) {
+                        return Err(invalid(format!("invalid memory size {}", mem.len())));
+                    }
+                    cpu.ram = Memory::from_bytes(mem.to_vec());
+                }
+                // Applied once the resolution is known
+                VRAM => {
+                    vram = chunk.take(HIRES_WIDTH * HIRES_HEIGHT)?;
+                    let planes_mask = ((1 << PLANES) - 1) as u8;
+                    if vram.iter().any(|pixel| pixel & !planes_mask != 0) {
+                        return Err(invalid("vram contains pixels outside of the bitplanes"));
+                    }
+                }
+                RPL_FLAGS => cpu.rpl = chunk.array()?,
+                AUDIO => {
+                    cpu.audio_pattern = chunk.array()?;
+                    cpu.pitch = chunk.u8()?;
+                }
+                RANDOM_SOURCE => {
+                    let name = chunk.take(chunk.data.len())?;
+                    if name != cpu.rng.name().as_bytes() {
+                        return Err(invalid(format!(
+                            "state was saved with the {} random source, but {} is in use",
+                            String::from_utf8_lossy(name),
+                            cpu.rng.name()
+                        )));
+                    }
+                }
+                // Restored once the generator is known to match; states without it keep the generator as is
+                RANDOM => random = Some(chunk.take(chunk.data.len())?),
+                // Chunks added by later versions are skipped
+                _ => continue,
+            }
+            seen.push(chunk.tag);
+            chunk.finish()?;
+        }
+
+        for required in [QUIRKS, REGISTERS, STACK, MEMORY, VRAM] {
+            if !seen.contains(&required) {
+                return Err(invalid(format!(
+                    "missing chunk {}",
+                    String::from_utf8_lossy(required)
+                )));
+            }
+        }
+
+        if let Some(state) = random {
+            cpu.rng.restore(state)?;
+        }
+
+        cpu.vram = if cpu.hires {
+            Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            Framebuffer::new(WIDTH, HEIGHT)
+        };
+        for x in 0..cpu.vram.width() {
+            for y in 0..cpu.vram.height() {
+                cpu.vram.set(x, y, vram[x * HIRES_HEIGHT + y]);
+            }
+        }
+
+        cpu.validate()?;
+        Ok(cpu)
+    }
+
+    // Checks that the restored registers are consistent with each other
+    fn validate(&self) -> Result<()> {
+        let size = self.ram.size();
+        let expected = if self.quirks.extended_memory { 0x10000 } else { 0x1000 };
+        if size != expected {
+            return Err(invalid(format!(
+                "memory size {size} does not match the extended memory quirk"
+            )));
+        }
+        if usize::from(self.pc) >= size {
+            return Err(invalid(format!("pc {:#X} is outside of memory", self.pc)));
+        }
+        if self.stack.len() > self.stack_limit {
+            return Err(invalid(format!(
+                "stack depth {} exceeds the limit of {}",
+                self.stack.len(),
+                self.stack_limit
+            )));
+        }
+        if let Some(addr) = self.stack.iter().find(|addr| usize::from(**addr) >= size) {
+            return Err(invalid(format!("return address {addr:#X} is outside of memory")));
+        }
+        Ok(())
+    }
+}
+
+/// Writes a save state of ``cpu`` to ``filename``
+pub fn save_to_file(cpu: &CPU, filename: &str) -> Result<()> {
+    fs::write(filename, cpu.save_state())
+        .map_err(|err| invalid(format!("could not write {filename}: {err}")))
+}
+
+/// Reads a save state from ``filename``
+pub fn load_from_file(filename: &str) -> Result<CPU> {
+    CPU::load_state(&read_file(filename)?)
+}
+
+/// Restores a save state from ``filename`` into ``cpu``, keeping its random number generator
+pub fn restore_from_file(cpu: &mut CPU, filename: &str) -> Result<()> {
+    cpu.restore_state(&read_file(filename)?)
+}
+
+fn read_file(filename: &str) -> Result<Vec<u8>> {
+    fs::read(filename).map_err(|err| invalid(format!("could not read {filename}: {err}")))
+}
+
+#[cfg(test)]
+pub mod savestate_tests {
+    use super::*;
+    use crate::quirks::Platform;
+    use crate::random::random_tests::CountingRng;
+
+    fn running_cpu() -> CPU {
+        // Call a subroutine that sets V0, I and the delay timer, then draws the "0" glyph
+        let mut cpu = CPU::with_quirks(Platform::XoChip.quirks());
+        cpu.load_program(&[
+            0x22, 0x04, 0x12, 0x02, 0x60, 0x2A, 0xF0, 0x15, 0xA0, 0x00, 0xD0, 0x05, 0x00, 0xEE,
+        ])
+        .unwrap();
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let cpu = running_cpu();
+        let restored = CPU::load_state(&cpu.save_state()).unwrap();
+        assert_eq!(restored.save_state(), cpu.save_state());
+        assert_eq!(restored.program_counter(), cpu.program_counter());
+        assert_eq!(restored.vs, cpu.vs);
+        assert_eq!(restored.delay, 0x2A);
+        assert_eq!(restored.stack, vec![0x202]);
+        assert_eq!(restored.vram, cpu.vram);
+        assert_eq!(restored.quirks(), Platform::XoChip.quirks());
+        assert_eq!(restored.ram().bytes(), cpu.ram().bytes());
+    }
+
+    #[test]
+    pub fn test_restored_cpu_continues_identically() {
+        let mut cpu = running_cpu();
+        let mut restored = CPU::load_state(&cpu.save_state()).unwrap();
+        for _ in 0..4 {
+            cpu.step().unwrap();
+            restored.step().unwrap();
+        }
+        assert_eq!(restored.save_state(), cpu.save_state());
+    }
+
+    #[test]
+    pub fn test_rejects_bad_header() {
+        let mut state = running_cpu().save_state();
+        state[0] = b'X';
+        assert!(CPU::load_state(&state).is_err());
+
+        let mut state = running_cpu().save_state();
+        state[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        assert!(CPU::load_state(&state).is_err());
+    }
+
+    #[test]
+    pub fn test_rejects_truncated_state() {
+        let state = running_cpu().save_state();
+        assert!(CPU::load_state(&state[..state.len() - 1]).is_err());
+    }
+
+    #[test]
+    pub fn test_rejects_inconsistent_state() {
+        let mut cpu = running_cpu();
+        cpu.stack = vec![0x200; 17];
+        assert!(CPU::load_state(&cpu.save_state()).is_err());
+
+        let mut cpu = running_cpu();
+        cpu.pc = 0xFFFF;
+        cpu.quirks.extended_memory = false;
+        assert!(CPU::load_state(&cpu.save_state()).is_err());
+    }
+
+    #[test]
+    pub fn test_skips_unknown_chunks() {
+        let cpu = running_cpu();
+        let mut writer = StateWriter {
+            data: cpu.save_state(),
+        };
+        writer.chunk(b"XTRA", &[1, 2, 3]);
+        let restored = CPU::load_state(&writer.data).unwrap();
+        assert_eq!(restored.save_state(), cpu.save_state());
+    }
+
+    #[test]
+    pub fn test_random_state_is_restored() {
+        // V0 = rand & 0xFF
+        let mut cpu = running_cpu();
+        cpu.set_seed(7);
+        let mut restored = CPU::load_state(&cpu.save_state()).unwrap();
+        for cpu in [&mut cpu, &mut restored] {
+            cpu.execute_instr((0xC0, 0xFF), crate::cpu::NO_KEYS).unwrap();
+        }
+        assert_eq!(restored.vs[0], cpu.vs[0]);
+    }
+
+    #[test]
+    pub fn test_rejects_other_random_source() {
+        let mut cpu = running_cpu();
+        cpu.set_rng(Box::new(CountingRng { next: 0 }));
+        let state = cpu.save_state();
+
+        let mut other = running_cpu();
+        assert!(other.restore_state(&state).is_err());
+        assert!(CPU::load_state(&state).is_err());
+        assert!(CPU::load_state_with_rng(&state, Box::new(CountingRng { next: 9 })).is_ok());
+    }
+}
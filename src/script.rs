@@ -0,0 +1,115 @@
+//! Scripted keypad input for unattended runs.
+//!
+//! A key script is a list of ``FRAME:KEYS`` entries separated by commas or newlines. From frame
+//! ``FRAME`` onwards the keys held are exactly the hex digits in ``KEYS``, until the next entry;
+//! ``-`` releases every key. For example ``0:5,30:-,45:46`` holds 5 for the first 30 frames,
+//! releases it for 15 frames, then holds 4 and 6 for the rest of the run. Text after a ``#`` is
+//! a comment.
+
+use crate::cpu::{KeyState, NO_KEYS};
+use crate::error::*;
+use crate::frontend::{Command, InputSource};
+
+/// Plays back a key script, one entry per frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    events: Vec<(u64, KeyState)>, // the keys held from each frame onwards, ordered by frame
+    frame: u64,                   // frames polled so far
+    held: KeyState,
+}
+
+fn script_error(reason: impl Into<String>) -> Chip8Error {
+    Chip8Error::KeyScriptError(reason.into())
+}
+
+impl KeyScript {
+    /* Parses a key script, rejecting unknown keys and entries that go back in time */
+    pub fn parse(script: &str) -> Result<KeyScript> {
+        let mut events: Vec<(u64, KeyState)> = Vec::new();
+        let entries = script
+            .lines()
+            .map(|line| line.split('#').next().unwrap())
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty());
+
+        for entry in entries {
+            let (frame, keys) = entry
+                .split_once(':')
+                .ok_or_else(|| script_error(format!("{entry} is not of the form FRAME:KEYS")))?;
+            let frame: u64 = frame
+                .trim()
+                .parse()
+                .map_err(|_| script_error(format!("{frame} is not a frame number")))?;
+            if events.last().is_some_and(|(last, _)| *last >= frame) {
+                return Err(script_error(format!("frame {frame} is out of order")));
+            }
+
+            let mut state = NO_KEYS;
+            let keys = keys.trim();
+            if keys != "-" {
+                for key in keys.chars() {
+                    let key = key
+                        .to_digit(16)
+                        .ok_or_else(|| script_error(format!("{key} is not a hex key")))?;
+                    state[key as usize] = true;
+                }
+            }
+            events.push((frame, state));
+        }
+
+        Ok(KeyScript {
+            events,
+            frame: 0,
+            held: NO_KEYS,
+        })
+    }
+
+    /* Returns the frame that will be played by the next poll */
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+impl InputSource for KeyScript {
+    fn poll(&mut self) -> Vec<Command> {
+        if let Some((_, state)) = self.events.iter().rev().find(|(frame, _)| *frame <= self.frame) {
+            self.held = *state;
+        }
+        self.frame += 1;
+        Vec::new()
+    }
+
+    fn keystate(&self) -> KeyState {
+        self.held
+    }
+}
+
+#[cfg(test)]
+pub mod script_tests {
+    use super::*;
+
+    #[test]
+    pub fn test_playback() {
+        let mut script = KeyScript::parse("1:5, 3:4A # press\n5:-").unwrap();
+        let mut held = Vec::new();
+        for _ in 0..6 {
+            script.poll();
+            let keys: Vec<usize> = (0..16).filter(|&key| script.keystate()[key]).collect();
+            held.push(keys);
+        }
+        assert_eq!(
+            held,
+            vec![vec![], vec![5], vec![5], vec![4, 10], vec![4, 10], vec![]]
+        );
+    }
+
+    #[test]
+    pub fn test_invalid_scripts() {
+        assert!(KeyScript::parse("").is_ok());
+        assert!(KeyScript::parse("5").is_err());
+        assert!(KeyScript::parse("x:5").is_err());
+        assert!(KeyScript::parse("0:G").is_err());
+        assert!(KeyScript::parse("5:1,5:2").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use crate::audio::{Tone, ToneSettings, SAMPLE_RATE};
+use crate::cpu::CPU;
+use crate::error::*;
+use crate::frontend::AudioSink;
+
+// Samples per callback; small enough that the tone starts within a frame of the sound timer being set
+const BUFFER_SAMPLES: u16 = 512;
+
+/// Feeds SDL's audio thread from a ``Tone``
+pub struct ToneCallback(Tone);
+
+impl AudioCallback for ToneCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+/// Plays the sound timer's tone through SDL, or stays silent if it was created without a device
+pub struct SdlAudio {
+    device: Option<AudioDevice<ToneCallback>>,
+}
+
+impl SdlAudio {
+    /* Opens the default audio device and starts playing the tone whenever the sound timer is set */
+    pub fn new(audio_subsystem: &AudioSubsystem, settings: ToneSettings, muted: bool) -> Result<Self> {
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: Some(BUFFER_SAMPLES),
+        };
+        let device = audio_subsystem.open_playback(None, &spec, |spec| {
+            let mut tone = Tone::new(settings, spec.freq as u32);
+            tone.set_muted(muted);
+            ToneCallback(tone)
+        });
+        let device = device
+            .map_err(|err| Chip8Error::AudioError(format!("could not open an audio device: {}", err)))?;
+        device.resume();
+        Ok(SdlAudio { device: Some(device) })
+    }
+
+    /* Creates an output that never sounds, for when no audio device could be opened */
+    pub fn silent() -> Self {
+        SdlAudio { device: None }
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn frame(&mut self, cpu: &CPU, sounding: bool) -> Result<()> {
+        if let Some(device) = &mut self.device {
+            device.lock().0.update(cpu, sounding);
+        }
+        Ok(())
+    }
+
+    fn toggle_mute(&mut self) -> Option<bool> {
+        let device = self.device.as_mut()?;
+        let mut callback = device.lock();
+        let muted = !callback.0.is_muted();
+        callback.0.set_muted(muted);
+        Some(muted)
+    }
+}
@@ -0,0 +1,206 @@
+//! Human-readable formatting of opcodes.
+//!
+//! ``Opcode`` implements ``Display`` in Cowgod's syntax (``LD V3, 0x10``); the alternate flag
+//! (``{:#}``) selects Octo's syntax (``v3 := 0x10``) instead.
+
+use crate::cpu::Opcode;
+use crate::error::*;
+use std::fmt;
+use std::str::FromStr;
+use ux::u4;
+
+/// Mnemonic syntaxes opcodes can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Syntax {
+    /// The mnemonics of Cowgod's technical reference, e.g. ``DRW V0, V1, 5``
+    #[default]
+    Cowgod,
+    /// The syntax of the Octo assembler, e.g. ``sprite v0 v1 5``
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<Syntax> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(Chip8Error::CommandParseError(format!("unknown syntax {s}"))),
+        }
+    }
+}
+
+fn addr(addr: u16) -> String {
+    format!("0x{:03X}", addr)
+}
+
+impl Opcode {
+    /* Formats the opcode in ``syntax``, with addresses written as hex numbers */
+    pub fn format(&self, syntax: Syntax) -> String {
+        self.format_with(syntax, addr)
+    }
+
+    /* Formats the opcode in ``syntax``, naming the addresses it refers to with ``target``.
+    LongSetI is formatted without its address, which follows it in memory */
+    pub fn format_with(&self, syntax: Syntax, target: impl Fn(u16) -> String) -> String {
+        match syntax {
+            Syntax::Cowgod => self.cowgod(target),
+            Syntax::Octo => self.octo(target),
+        }
+    }
+
+    fn cowgod(&self, target: impl Fn(u16) -> String) -> String {
+        let v = |nib: u4| format!("V{:X}", u8::from(nib));
+        match *self {
+            Opcode::ClearScreen => "CLS".to_string(),
+            Opcode::Return => "RET".to_string(),
+            Opcode::ScrollDown(n) => format!("SCD {}", u8::from(n)),
+            Opcode::ScrollRight => "SCR".to_string(),
+            Opcode::ScrollLeft => "SCL".to_string(),
+            Opcode::Exit => "EXIT".to_string(),
+            Opcode::LowRes => "LOW".to_string(),
+            Opcode::HighRes => "HIGH".to_string(),
+            Opcode::Jump(nnn) => format!("JP {}", target(u16::from(nnn))),
+            Opcode::JumpOffset(nnn) => format!("JP V0, {}", target(u16::from(nnn))),
+            Opcode::CallSubroutine(nnn) => format!("CALL {}", target(u16::from(nnn))),
+            Opcode::SkipEqImm(x, nn) => format!("SE {}, 0x{:02X}", v(x), nn),
+            Opcode::SkipNeqImm(x, nn) => format!("SNE {}, 0x{:02X}", v(x), nn),
+            Opcode::SkipEqReg(x, y) => format!("SE {}, {}", v(x), v(y)),
+            Opcode::SkipNeqReg(x, y) => format!("SNE {}, {}", v(x), v(y)),
+            Opcode::SetReg(x, nn) => format!("LD {}, 0x{:02X}", v(x), nn),
+            Opcode::AddReg(x, nn) => format!("ADD {}, 0x{:02X}", v(x), nn),
+            Opcode::Set(x, y) => format!("LD {}, {}", v(x), v(y)),
+            Opcode::Or(x, y) => format!("OR {}, {}", v(x), v(y)),
+            Opcode::And(x, y) => format!("AND {}, {}", v(x), v(y)),
+            Opcode::Xor(x, y) => format!("XOR {}, {}", v(x), v(y)),
+            Opcode::Add(x, y) => format!("ADD {}, {}", v(x), v(y)),
+            Opcode::Subtract1(x, y) => format!("SUB {}, {}", v(x), v(y)),
+            Opcode::ShiftR(x, y) => format!("SHR {}, {}", v(x), v(y)),
+            Opcode::Subtract2(x, y) => format!("SUBN {}, {}", v(x), v(y)),
+            Opcode::ShiftL(x, y) => format!("SHL {}, {}", v(x), v(y)),
+            Opcode::SetI(nnn) => format!("LD I, {}", target(u16::from(nnn))),
+            Opcode::Random(x, nn) => format!("RND {}, 0x{:02X}", v(x), nn),
+            Opcode::Display(x, y, n) => format!("DRW {}, {}, {}", v(x), v(y), u8::from(n)),
+            Opcode::SkipIfKey(x) => format!("SKP {}", v(x)),
+            Opcode::SkipIfNotKey(x) => format!("SKNP {}", v(x)),
+            Opcode::SetRegToDelay(x) => format!("LD {}, DT", v(x)),
+            Opcode::GetKey(x) => format!("LD {}, K", v(x)),
+            Opcode::SetDelayToReg(x) => format!("LD DT, {}", v(x)),
+            Opcode::SetSoundToReg(x) => format!("LD ST, {}", v(x)),
+            Opcode::AddToIndex(x) => format!("ADD I, {}", v(x)),
+            Opcode::Font(x) => format!("LD F, {}", v(x)),
+            Opcode::BigFont(x) => format!("LD HF, {}", v(x)),
+            Opcode::Decimal(x) => format!("LD B, {}", v(x)),
+            Opcode::Store(x) => format!("LD [I], {}", v(x)),
+            Opcode::Load(x) => format!("LD {}, [I]", v(x)),
+            Opcode::SaveFlags(x) => format!("LD R, {}", v(x)),
+            Opcode::LoadFlags(x) => format!("LD {}, R", v(x)),
+            Opcode::SaveRange(x, y) => format!("SAVE {}, {}", v(x), v(y)),
+            Opcode::LoadRange(x, y) => format!("LOAD {}, {}", v(x), v(y)),
+            Opcode::LongSetI => "LD I, LONG".to_string(),
+            Opcode::Plane(n) => format!("PLANE {}", u8::from(n)),
+            Opcode::AudioPattern => "AUDIO".to_string(),
+            Opcode::Pitch(x) => format!("PITCH {}", v(x)),
+        }
+    }
+
+    // Octo writes skips as the condition under which the next instruction runs, so they read inverted
+    fn octo(&self, target: impl Fn(u16) -> String) -> String {
+        let v = |nib: u4| format!("v{:x}", u8::from(nib));
+        match *self {
+            Opcode::ClearScreen => "clear".to_string(),
+            Opcode::Return => "return".to_string(),
+            Opcode::ScrollDown(n) => format!("scroll-down {}", u8::from(n)),
+            Opcode::ScrollRight => "scroll-right".to_string(),
+            Opcode::ScrollLeft => "scroll-left".to_string(),
+            Opcode::Exit => "exit".to_string(),
+            Opcode::LowRes => "lores".to_string(),
+            Opcode::HighRes => "hires".to_string(),
+            Opcode::Jump(nnn) => format!("jump {}", target(u16::from(nnn))),
+            Opcode::JumpOffset(nnn) => format!("jump0 {}", target(u16::from(nnn))),
+            Opcode::CallSubroutine(nnn) => format!(":call {}", target(u16::from(nnn))),
+            Opcode::SkipEqImm(x, nn) => format!("if {} != 0x{:02X} then", v(x), nn),
+            Opcode::SkipNeqImm(x, nn) => format!("if {} == 0x{:02X} then", v(x), nn),
+            Opcode::SkipEqReg(x, y) => format!("if {} != {} then", v(x), v(y)),
+            Opcode::SkipNeqReg(x, y) => format!("if {} == {} then", v(x), v(y)),
+            Opcode::SetReg(x, nn) => format!("{} := 0x{:02X}", v(x), nn),
+            Opcode::AddReg(x, nn) => format!("{} += 0x{:02X}", v(x), nn),
+            Opcode::Set(x, y) => format!("{} := {}", v(x), v(y)),
+            Opcode::Or(x, y) => format!("{} |= {}", v(x), v(y)),
+            Opcode::And(x, y) => format!("{} &= {}", v(x), v(y)),
+            Opcode::Xor(x, y) => format!("{} ^= {}", v(x), v(y)),
+            Opcode::Add(x, y) => format!("{} += {}", v(x), v(y)),
+            Opcode::Subtract1(x, y) => format!("{} -= {}", v(x), v(y)),
+            Opcode::ShiftR(x, y) => format!("{} >>= {}", v(x), v(y)),
+            Opcode::Subtract2(x, y) => format!("{} =- {}", v(x), v(y)),
+            Opcode::ShiftL(x, y) => format!("{} <<= {}", v(x), v(y)),
+            Opcode::SetI(nnn) => format!("i := {}", target(u16::from(nnn))),
+            Opcode::Random(x, nn) => format!("{} := random 0x{:02X}", v(x), nn),
+            Opcode::Display(x, y, n) => format!("sprite {} {} {}", v(x), v(y), u8::from(n)),
+            Opcode::SkipIfKey(x) => format!("if {} -key then", v(x)),
+            Opcode::SkipIfNotKey(x) => format!("if {} key then", v(x)),
+            Opcode::SetRegToDelay(x) => format!("{} := delay", v(x)),
+            Opcode::GetKey(x) => format!("{} := key", v(x)),
+            Opcode::SetDelayToReg(x) => format!("delay := {}", v(x)),
+            Opcode::SetSoundToReg(x) => format!("buzzer := {}", v(x)),
+            Opcode::AddToIndex(x) => format!("i += {}", v(x)),
+            Opcode::Font(x) => format!("i := hex {}", v(x)),
+            Opcode::BigFont(x) => format!("i := bighex {}", v(x)),
+            Opcode::Decimal(x) => format!("bcd {}", v(x)),
+            Opcode::Store(x) => format!("save {}", v(x)),
+            Opcode::Load(x) => format!("load {}", v(x)),
+            Opcode::SaveFlags(x) => format!("saveflags {}", v(x)),
+            Opcode::LoadFlags(x) => format!("loadflags {}", v(x)),
+            Opcode::SaveRange(x, y) => format!("save {} - {}", v(x), v(y)),
+            Opcode::LoadRange(x, y) => format!("load {} - {}", v(x), v(y)),
+            Opcode::LongSetI => "i := long".to_string(),
+            Opcode::Plane(n) => format!("plane {}", u8::from(n)),
+            Opcode::AudioPattern => "audio".to_string(),
+            Opcode::Pitch(x) => format!("pitch := {}", v(x)),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let syntax = if f.alternate() { Syntax::Octo } else { Syntax::Cowgod };
+        f.pad(&self.format(syntax))
+    }
+}
+
+#[cfg(test)]
+pub mod syntax_tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    fn decode(word: u16) -> Opcode {
+        CPU::new().try_decode((word.to_be_bytes()[0], word.to_be_bytes()[1])).unwrap()
+    }
+
+    #[test]
+    pub fn test_display() {
+        assert_eq!(decode(0x6310).to_string(), "LD V3, 0x10");
+        assert_eq!(decode(0xD015).to_string(), "DRW V0, V1, 5");
+        assert_eq!(decode(0xE49E).to_string(), "SKP V4");
+        assert_eq!(decode(0x1234).to_string(), "JP 0x234");
+        assert_eq!(format!("{:#}", decode(0x6310)), "v3 := 0x10");
+        assert_eq!(format!("{:#}", decode(0x3A01)), "if va != 0x01 then");
+        assert_eq!(format!("{:<8}|", decode(0x00E0)), "CLS     |");
+    }
+
+    #[test]
+    pub fn test_cowgod_assembles_back() {
+        // Everything the Cowgod syntax prints must be accepted by the assembler
+        let cpu = CPU::new();
+        for word in (0..=u16::MAX).step_by(7) {
+            let [byte_1, byte_2] = word.to_be_bytes();
+            let Ok(opcode) = cpu.try_decode((byte_1, byte_2)) else { continue };
+            if opcode == Opcode::LongSetI {
+                continue;
+            }
+            let program = crate::assembler::Assembler::assemble(&opcode.to_string()).unwrap();
+            assert_eq!(program.bytes, vec![byte_1, byte_2], "{}", opcode);
+        }
+    }
+}
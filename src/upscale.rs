@@ -0,0 +1,256 @@
+//! Pixel-art upscaling filters and the settings for fitting the display into a window.
+//!
+//! Filters work on the coloured display, after the palette and phosphor persistence have been
+//! applied, and enlarge it by a whole factor. The renderer then scales the result the rest of
+//! the way to the window.
+
+use crate::error::*;
+use crate::palette::Rgb;
+use std::str::FromStr;
+
+// Largest factor any filter enlarges the display by
+pub const MAX_FACTOR: usize = 3;
+
+/// How the display is enlarged before it is scaled to the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// Every pixel becomes a square block
+    #[default]
+    Nearest,
+    /// Rounds off diagonal edges, doubling the size
+    Scale2x,
+    /// Rounds off diagonal edges, tripling the size
+    Scale3x,
+    /// Eric's Pixel Expansion, which Scale2x refines; it also smooths corners next to lines
+    Epx,
+}
+
+impl FromStr for Filter {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<Filter> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            "epx" => Ok(Filter::Epx),
+            _ => Err(Chip8Error::CommandParseError(format!("unknown filter {s}"))),
+        }
+    }
+}
+
+impl Filter {
+    /* Returns how many times wider and taller the filter makes the display */
+    pub fn factor(&self) -> usize {
+        match self {
+            Filter::Nearest => 1,
+            Filter::Scale2x | Filter::Epx => 2,
+            Filter::Scale3x => 3,
+        }
+    }
+
+    /* Enlarges the ``width`` x ``height`` image in ``pixels``, stored row by row, by ``factor()``.
+    Pixels beyond the edges are treated as copies of the nearest edge pixel */
+    pub fn apply(&self, pixels: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+        let mut out = Vec::new();
+        self.apply_into(pixels, width, height, &mut out);
+        out
+    }
+
+    /* Like ``apply``, but writes the enlarged image into ``out``, reusing its allocation */
+    pub fn apply_into(&self, pixels: &[Rgb], width: usize, height: usize, out: &mut Vec<Rgb>) {
+        let factor = self.factor();
+        let out_width = width * factor;
+        out.clear();
+        out.resize(out_width * height * factor, [0; 3]);
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, width as isize - 1) as usize;
+            let y = y.clamp(0, height as isize - 1) as usize;
+            pixels[y * width + x]
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let (xi, yi) = (x as isize, y as isize);
+                // The first factor * factor entries hold the enlarged pixel, row by row
+                let mut block = [[0; 3]; MAX_FACTOR * MAX_FACTOR];
+                match self {
+                    Filter::Nearest => block[0] = at(xi, yi),
+                    Filter::Scale2x => {
+                        let (b, d, e, f, h) =
+                            (at(xi, yi - 1), at(xi - 1, yi), at(xi, yi), at(xi + 1, yi), at(xi, yi + 1));
+                        block[..4].copy_from_slice(&[
+                            if d == b && b != f && d != h { d } else { e },
+                            if b == f && b != d && f != h { f } else { e },
+                            if d == h && d != b && h != f { d } else { e },
+                            if h == f && d != h && b != f { f } else { e },
+                        ]);
+                    }
+                    Filter::Scale3x => {
+                        let (a, b, c) = (at(xi - 1, yi - 1), at(xi, yi - 1), at(xi + 1, yi - 1));
+                        let (d, e, f) = (at(xi - 1, yi), at(xi, yi), at(xi + 1, yi));
+                        let (g, h, i) = (at(xi - 1, yi + 1), at(xi, yi + 1), at(xi + 1, yi + 1));
+                        // The four diagonal edges Scale2x detects
+                        let top_left = d == b && b != f && d != h;
+                        let top_right = b == f && b != d && f != h;
+                        let bottom_left = d == h && d != b && h != f;
+                        let bottom_right = h == f && d != h && b != f;
+                        block = [
+                            if top_left { d } else { e },
+                            if (top_left && e != c) || (top_right && e != a) { b } else { e },
+                            if top_right { f } else { e },
+                            if (top_left && e != g) || (bottom_left && e != a) { d } else { e },
+                            e,
+                            if (top_right && e != i) || (bottom_right && e != c) { f } else { e },
+                            if bottom_left { d } else { e },
+                            if (bottom_left && e != i) || (bottom_right && e != g) { h } else { e },
+                            if bottom_right { f } else { e },
+                        ];
+                    }
+                    Filter::Epx => {
+                        let p = at(xi, yi);
+                        let (a, b, c, d) = (at(xi, yi - 1), at(xi + 1, yi), at(xi - 1, yi), at(xi, yi + 1));
+                        let neighbours = [a, b, c, d];
+                        let crowded = neighbours
+                            .iter()
+                            .any(|n| neighbours.iter().filter(|other| *other == n).count() >= 3);
+                        if crowded {
+                            block[..4].fill(p);
+                        } else {
+                            block[..4].copy_from_slice(&[
+                                if c == a { a } else { p },
+                                if a == b { b } else { p },
+                                if d == c { c } else { p },
+                                if b == d { d } else { p },
+                            ]);
+                        }
+                    }
+                }
+                for (i, color) in block[..factor * factor].iter().enumerate() {
+                    let (dx, dy) = (i % factor, i / factor);
+                    out[(y * factor + dy) * out_width + x * factor + dx] = *color;
+                }
+            }
+        }
+    }
+}
+
+/// Lines drawn over the scaled display to imitate a screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlay {
+    #[default]
+    None,
+    /// Darkens the lower part of every row of pixels
+    Scanlines,
+    /// Outlines every pixel
+    Grid,
+}
+
+impl FromStr for Overlay {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<Overlay> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(Overlay::None),
+            "scanlines" => Ok(Overlay::Scanlines),
+            "grid" => Ok(Overlay::Grid),
+            _ => Err(Chip8Error::CommandParseError(format!("unknown overlay {s}"))),
+        }
+    }
+}
+
+/// How the display is scaled to the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// As large as fits while keeping the aspect ratio
+    #[default]
+    Fit,
+    /// The largest whole multiple of the display's size that fits, so every pixel is the same size
+    Integer,
+}
+
+impl FromStr for ScaleMode {
+    type Err = Chip8Error;
+
+    fn from_str(s: &str) -> Result<ScaleMode> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "fit" => Ok(ScaleMode::Fit),
+            "integer" => Ok(ScaleMode::Integer),
+            _ => Err(Chip8Error::CommandParseError(format!("unknown scale mode {s}"))),
+        }
+    }
+}
+
+impl ScaleMode {
+    /* Returns the (x, y, width, height) of the area a ``width`` x ``height`` display covers in
+    a window of ``window_width`` x ``window_height``, centred */
+    pub fn viewport(&self, width: u32, height: u32, window_width: u32, window_height: u32) -> (i32, i32, u32, u32) {
+        let (view_width, view_height) = match self {
+            ScaleMode::Fit => {
+                let scale = f64::min(
+                    f64::from(window_width) / f64::from(width),
+                    f64::from(window_height) / f64::from(height),
+                );
+                ((f64::from(width) * scale) as u32, (f64::from(height) * scale) as u32)
+            }
+            ScaleMode::Integer => {
+                // Windows smaller than the display still show it, shrunk to fit
+                let scale = u32::min(window_width / width, window_height / height);
+                if scale == 0 {
+                    return ScaleMode::Fit.viewport(width, height, window_width, window_height);
+                }
+                (width * scale, height * scale)
+            }
+        };
+        let x = (window_width - view_width) / 2;
+        let y = (window_height - view_height) / 2;
+        (x as i32, y as i32, view_width, view_height)
+    }
+}
+
+/// Settings for enlarging the display to the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Scaling {
+    pub filter: Filter,
+    pub overlay: Overlay,
+    pub mode: ScaleMode,
+}
+
+#[cfg(test)]
+pub mod upscale_tests {
+    use super::*;
+
+    const O: Rgb = [0, 0, 0];
+    const X: Rgb = [255, 255, 255];
+
+    #[test]
+    pub fn test_filters() {
+        // A diagonal line
+        let pixels = [X, O, O, X];
+        assert_eq!(Filter::Nearest.apply(&pixels, 2, 2), pixels);
+
+        let scaled = Filter::Scale2x.apply(&pixels, 2, 2);
+        assert_eq!(scaled.len(), 16);
+        assert_eq!(scaled[4..8], [X, O, X, O]);
+        assert_eq!(Filter::Scale3x.apply(&pixels, 2, 2).len(), 36);
+
+        // A lone pixel stays square
+        let pixels = [O, O, O, O, X, O, O, O, O];
+        let scaled = Filter::Epx.apply(&pixels, 3, 3);
+        assert_eq!(scaled[2 * 6 + 2..2 * 6 + 4], [X, X]);
+        assert_eq!(scaled[3 * 6 + 2..3 * 6 + 4], [X, X]);
+        assert_eq!(scaled.iter().filter(|pixel| **pixel == X).count(), 4);
+
+        // Reusing a buffer from a larger image gives the same result
+        let mut out = vec![X; 100];
+        Filter::Epx.apply_into(&pixels, 3, 3, &mut out);
+        assert_eq!(out, scaled);
+    }
+
+    #[test]
+    pub fn test_viewport() {
+        assert_eq!(ScaleMode::Fit.viewport(64, 32, 1000, 1000), (0, 250, 1000, 500));
+        assert_eq!(ScaleMode::Integer.viewport(64, 32, 1000, 1000), (20, 260, 960, 480));
+        assert_eq!(ScaleMode::Integer.viewport(64, 32, 32, 32), (0, 8, 32, 16));
+    }
+}
@@ -0,0 +1,115 @@
+//! An audio sink that records the sound of a run into a 16-bit mono PCM WAV file, so that the
+//! audio of headless runs can be compared between versions.
+
+use crate::audio::{Tone, ToneSettings, SAMPLE_RATE};
+use crate::cpu::CPU;
+use crate::error::*;
+use crate::frontend::AudioSink;
+use crate::machine::FRAME_RATE;
+use std::fs;
+
+/// Renders one frame of audio per call to ``frame`` into memory
+#[derive(Debug, Clone)]
+pub struct WavSink {
+    tone: Tone,
+    sample_rate: u32,
+    frames: u64,
+    samples: Vec<i16>,
+}
+
+impl WavSink {
+    pub fn new(settings: ToneSettings) -> Self {
+        WavSink::with_sample_rate(settings, SAMPLE_RATE)
+    }
+
+    pub fn with_sample_rate(settings: ToneSettings, sample_rate: u32) -> Self {
+        WavSink {
+            tone: Tone::new(settings, sample_rate),
+            sample_rate,
+            frames: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    /* Encodes the recorded samples as a WAV file */
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = (self.samples.len() * 2) as u32;
+        let mut data = b"RIFF".to_vec();
+        data.extend((36 + data_len).to_le_bytes());
+        data.extend(b"WAVE");
+
+        data.extend(b"fmt ");
+        data.extend(16u32.to_le_bytes());
+        data.extend(1u16.to_le_bytes()); // PCM
+        data.extend(1u16.to_le_bytes()); // mono
+        data.extend(self.sample_rate.to_le_bytes());
+        data.extend((self.sample_rate * 2).to_le_bytes()); // bytes per second
+        data.extend(2u16.to_le_bytes()); // bytes per sample
+        data.extend(16u16.to_le_bytes()); // bits per sample
+
+        data.extend(b"data");
+        data.extend(data_len.to_le_bytes());
+        for sample in &self.samples {
+            data.extend(sample.to_le_bytes());
+        }
+        data
+    }
+
+    /* Writes the recorded samples to the WAV file ``filename`` */
+    pub fn save(&self, filename: &str) -> Result<()> {
+        fs::write(filename, self.to_wav())
+            .map_err(|err| Chip8Error::OutputError(format!("could not write {filename}: {err}")))
+    }
+}
+
+impl AudioSink for WavSink {
+    fn frame(&mut self, cpu: &CPU, sounding: bool) -> Result<()> {
+        self.tone.update(cpu, sounding);
+        // Frames don't span a whole number of samples, so the remainder is carried into later frames
+        self.frames += 1;
+        let end = self.frames * u64::from(self.sample_rate) / u64::from(FRAME_RATE);
+        let mut buffer = vec![0.0; end as usize - self.samples.len()];
+        self.tone.fill(&mut buffer);
+        self.samples
+            .extend(buffer.iter().map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod wav_tests {
+    use super::*;
+    use crate::frontend::{Frontend, NullDisplay, NullInput};
+    use crate::machine::Machine;
+
+    #[test]
+    pub fn test_records_sound_timer() {
+        // V0 = 3, sound timer = V0, loop forever
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0x60, 0x03, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let mut machine = Machine::new(cpu);
+        let sink = WavSink::new(ToneSettings::default());
+        let mut frontend = Frontend::new(NullDisplay, sink, NullInput).unthrottled();
+        for _ in 0..6 {
+            frontend.frame(&mut machine).unwrap();
+        }
+
+        // The tone sounds for exactly 3 frames, then fades out early in the 4th
+        let samples = frontend.audio.samples();
+        assert_eq!(samples.len(), 6 * 735);
+        let frames: Vec<&[i16]> = samples.chunks(735).collect();
+        assert!(frames[..3].iter().all(|frame| frame.iter().all(|sample| *sample != 0)));
+        assert!(frames[3][..735 / 2].iter().any(|sample| *sample != 0));
+        assert!(frames[3][735 / 2..].iter().all(|sample| *sample == 0));
+        assert!(frames[4..].iter().all(|frame| frame.iter().all(|sample| *sample == 0)));
+
+        let wav = frontend.audio.to_wav();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav.len(), 44 + 2 * 6 * 735);
+    }
+}